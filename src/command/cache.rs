@@ -0,0 +1,60 @@
+use chrono::{DateTime, Utc};
+use miette::Result;
+use tracing::instrument;
+
+use crate::io::cache::{Cache, Removed};
+
+#[derive(Debug)]
+pub struct Command {
+    cache: Cache,
+}
+
+impl Command {
+    pub const fn new(cache: Cache) -> Self {
+        Self { cache }
+    }
+
+    /// Print the cached audio, most recently used first, and how much there is
+    #[instrument]
+    pub async fn show(self) -> Result<()> {
+        let entries = self.cache.entries().await?;
+
+        for entry in &entries {
+            println!(
+                "{}  {:>10}  {}",
+                DateTime::<Utc>::from(entry.last_used).to_rfc3339(),
+                entry.size,
+                entry.key
+            );
+        }
+        println!(
+            "{} entries, {} bytes in {}",
+            entries.len(),
+            entries.iter().map(|entry| entry.size).sum::<u64>(),
+            self.cache.directory().display()
+        );
+
+        Ok(())
+    }
+
+    /// Remove audio that is too old, or that doesn't fit in the cache
+    #[instrument]
+    pub async fn prune(self) -> Result<()> {
+        print_removed(self.cache.prune().await?);
+        Ok(())
+    }
+
+    /// Remove all the cached audio
+    #[instrument]
+    pub async fn clear(self) -> Result<()> {
+        print_removed(self.cache.clear().await?);
+        Ok(())
+    }
+}
+
+fn print_removed(removed: Removed) {
+    println!(
+        "Removed {} entries, {} bytes",
+        removed.entries, removed.bytes
+    );
+}
@@ -0,0 +1,479 @@
+use std::{fmt::Debug, num::NonZeroUsize, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt, TryStreamExt};
+use html2text::render::text_renderer::TrivialDecorator;
+use miette::{IntoDiagnostic, Result};
+use quick_xml::escape as xml_escape;
+use reqwest::Url;
+use tokio::sync::Semaphore;
+use tracing::instrument;
+
+use crate::{
+    io::{
+        audio::{Audio, Format, Metadata, VecU8A},
+        output::{self, Template},
+        podcast::{self, Enclosure, Episode, Guid, Podcast},
+        state::State,
+        stream::Speaker,
+    },
+    remote::{
+        feed::{Feed, Item},
+        readability::{self, Repository as _},
+        translate::{AnyTranslator, Language, Translator},
+        tts::{AnyTts, Tts},
+    },
+    text,
+};
+
+#[derive(Debug)]
+pub struct Command {
+    tts: AnyTts,
+    concurrency: NonZeroUsize,
+    translator: Option<AnyTranslator>,
+    target_language: Language,
+    article_client: Option<readability::Reqwest>,
+    output: Option<Output>,
+    podcast_image: Option<Url>,
+    state_file: Option<PathBuf>,
+    // Each service only sees `concurrency` requests at once, however the work is split up, so
+    // batch runs stay inside the limits each plan allows
+    synthesizing: Semaphore,
+    translating: Semaphore,
+}
+
+#[derive(Debug)]
+struct Output {
+    directory: PathBuf,
+    template: Template,
+    podcast_base_url: Option<Url>,
+}
+
+/// An article's text, ready to be read
+#[derive(Debug)]
+struct Article {
+    item: Item,
+    index: usize,
+    text: String,
+    language: Option<Language>,
+}
+
+/// An article read aloud, in the pieces it was read in
+#[derive(Debug)]
+struct Narration {
+    article: Article,
+    chunks: Vec<String>,
+    /// Empty when the chunks are streamed to the speaker instead
+    audio: Vec<VecU8A>,
+}
+
+impl Command {
+    /// `concurrency` is how many articles are prepared, and how many requests are sent to each
+    /// service, at once
+    pub fn new(tts: AnyTts, target_language: Language, concurrency: NonZeroUsize) -> Self {
+        Self {
+            tts,
+            concurrency,
+            translator: None,
+            target_language,
+            article_client: None,
+            output: None,
+            podcast_image: None,
+            state_file: None,
+            synthesizing: Semaphore::new(concurrency.get()),
+            translating: Semaphore::new(concurrency.get()),
+        }
+    }
+
+    /// Translate articles that aren't already in the target language
+    #[must_use]
+    pub fn with_translator(mut self, translator: Option<AnyTranslator>) -> Self {
+        self.translator = translator;
+        self
+    }
+
+    /// Read the page each article links to rather than the feed's summary
+    #[must_use]
+    pub fn with_article_client(mut self, article_client: Option<readability::Reqwest>) -> Self {
+        self.article_client = article_client;
+        self
+    }
+
+    /// Save to files in `directory` rather than reading aloud, optionally writing a podcast feed
+    /// for them too
+    #[must_use]
+    pub fn with_output(
+        mut self,
+        directory: Option<PathBuf>,
+        template: Template,
+        podcast_base_url: Option<Url>,
+    ) -> Self {
+        self.output = directory.map(|directory| Output {
+            directory,
+            template,
+            podcast_base_url,
+        });
+        self
+    }
+
+    /// Show the artwork at `podcast_image` for the podcast feed
+    #[must_use]
+    pub fn with_podcast_image(mut self, podcast_image: Option<Url>) -> Self {
+        self.podcast_image = podcast_image;
+        self
+    }
+
+    /// Skip articles already narrated according to this file, and record the ones narrated now
+    #[must_use]
+    pub fn with_state_file(mut self, state_file: Option<PathBuf>) -> Self {
+        self.state_file = state_file;
+        self
+    }
+
+    /// Narrate each article in `feed`, the one found at `url`
+    ///
+    /// Articles are fetched, translated and synthesized several at a time, but are saved, played
+    /// and recorded in the order the feed lists them.
+    #[allow(
+        clippy::future_not_send,
+        reason = "Playback holds the output device, which can't be sent between threads"
+    )]
+    #[instrument(skip(feed))]
+    pub async fn run(self, url: &Url, feed: Feed) -> Result<()> {
+        let mut state = match &self.state_file {
+            Some(state_file) => Some(State::load(state_file).await?),
+            None => None,
+        };
+        let mut podcast = match &self.output {
+            Some(Output {
+                directory,
+                podcast_base_url: Some(_),
+                ..
+            }) => Some(
+                Podcast::load(directory.join(podcast::FILE_NAME))
+                    .await?
+                    .unwrap_or_else(|| {
+                        Podcast::new(
+                            feed.title
+                                .clone()
+                                .unwrap_or_else(|| "Story Time".to_string()),
+                            url.to_string(),
+                            format!("Narrated articles from {url}"),
+                            if self.translator.is_some() {
+                                Some(self.target_language.to_string())
+                            } else {
+                                feed.language.clone()
+                            },
+                        )
+                    })
+                    .with_image(self.podcast_image.as_ref()),
+            ),
+            _ => None,
+        };
+        // Without anywhere to save to, articles are read aloud as they are synthesized
+        let speaker = if self.output.is_none() {
+            Some(Speaker::try_new()?)
+        } else {
+            None
+        };
+
+        let feed_language = feed.language.clone().map(Language::from);
+        let run_started = Utc::now();
+        let streamed = speaker.is_some();
+
+        let items = feed
+            .items
+            .into_iter()
+            .enumerate()
+            .filter(|(_, item)| {
+                let narrated = state
+                    .as_ref()
+                    .is_some_and(|state| state.is_narrated(url, item));
+                if narrated {
+                    tracing::info!("Skipping {:?}, it has already been narrated", item.title);
+                }
+                !narrated
+            })
+            .collect::<Vec<_>>();
+
+        let mut narrations = stream::iter(items)
+            .map(|(index, item)| self.prepare(index, item, feed_language.as_ref()))
+            .buffered(self.concurrency.get())
+            .map_ok(|article| self.synthesize(article, streamed))
+            .try_buffered(self.concurrency.get());
+
+        while let Some(narration) = narrations.try_next().await? {
+            let item = match (&speaker, &self.output) {
+                (Some(speaker), _) => self.play(narration, speaker).await?,
+                (None, Some(output)) => {
+                    self.save(
+                        narration,
+                        output,
+                        feed.title.as_deref(),
+                        podcast.as_mut(),
+                        run_started,
+                    )
+                    .await?
+                }
+                (None, None) => narration.article.item,
+            };
+
+            if let (Some(state), Some(state_file)) = (&mut state, &self.state_file) {
+                state.mark_narrated(url, &item);
+                state.save(state_file).await?;
+            }
+        }
+
+        if let Some(speaker) = speaker {
+            speaker.finish().await;
+        }
+
+        Ok(())
+    }
+
+    /// Download, clean up and translate an article
+    #[instrument(skip(self))]
+    async fn prepare(
+        &self,
+        index: usize,
+        item: Item,
+        feed_language: Option<&Language>,
+    ) -> Result<Article> {
+        let mut buf = String::new();
+        if let Some(ref title) = item.title {
+            buf.push_str(title);
+            buf.push_str("\n\n");
+        }
+
+        let content = match (&self.article_client, &item.link) {
+            (Some(article_client), Some(link)) => {
+                full_article_or_summary(article_client, link, item.content.clone()).await
+            }
+            _ => item.content.clone(),
+        };
+        let decorator = TrivialDecorator::new();
+        let clean_text =
+            html2text::from_read_with_decorator(content.as_bytes(), usize::MAX, decorator);
+        buf.push_str(&clean_text);
+        buf.push_str("\n\n");
+
+        let Some(translator) = &self.translator else {
+            return Ok(Article {
+                item,
+                index,
+                text: buf,
+                language: feed_language.cloned(),
+            });
+        };
+
+        let source_language = feed_language
+            .cloned()
+            .or_else(|| text::language::detect(&buf));
+        let text = if source_language
+            .as_ref()
+            .is_some_and(|source| source.matches(&self.target_language))
+        {
+            tracing::info!(
+                "Not translating {:?}, it is already in {}",
+                item.title,
+                self.target_language
+            );
+            buf
+        } else {
+            let _permit = self.translating.acquire().await.into_diagnostic()?;
+            translator
+                .translate(buf, self.target_language.clone())
+                .await?
+                .text
+        };
+
+        Ok(Article {
+            item,
+            index,
+            text,
+            language: Some(self.target_language.clone()),
+        })
+    }
+
+    /// Split an article into pieces short enough to read, and read them unless they are going to
+    /// be streamed
+    #[instrument(skip(self))]
+    async fn synthesize(&self, article: Article, streamed: bool) -> Result<Narration> {
+        let chunks = text::chunk::chunk(
+            &article.text,
+            self.tts.max_characters(),
+            article.language.as_ref(),
+        )
+        .into_iter()
+        .map(|text| {
+            xml_escape::unescape(&text)
+                .map(|x| x.to_string())
+                .unwrap_or(text)
+        })
+        .collect::<Vec<_>>();
+
+        let audio = if streamed {
+            vec![]
+        } else {
+            stream::iter(&chunks)
+                .map(|text| async move {
+                    let _permit = self.synthesizing.acquire().await.into_diagnostic()?;
+                    self.tts.text_to_speech(text.clone()).await
+                })
+                .buffered(self.concurrency.get())
+                .try_collect()
+                .await?
+        };
+
+        Ok(Narration {
+            article,
+            chunks,
+            audio,
+        })
+    }
+
+    /// Read an article aloud, returning once its last piece is next to play
+    #[allow(
+        clippy::future_not_send,
+        reason = "Playback holds the output device, which can't be sent between threads"
+    )]
+    #[instrument(skip(self, narration))]
+    async fn play(&self, narration: Narration, speaker: &Speaker) -> Result<Item> {
+        for text in narration.chunks {
+            let audio = {
+                let _permit = self.synthesizing.acquire().await.into_diagnostic()?;
+                self.tts.stream_text_to_speech(text).await?
+            };
+            speaker.queue(audio).await?;
+        }
+
+        Ok(narration.article.item)
+    }
+
+    /// Save an article to the output directory, and add it to the podcast if there is one
+    #[instrument(skip(self, narration, podcast))]
+    async fn save(
+        &self,
+        narration: Narration,
+        output: &Output,
+        feed_title: Option<&str>,
+        podcast: Option<&mut Podcast>,
+        run_started: DateTime<Utc>,
+    ) -> Result<Item> {
+        let Narration { article, audio, .. } = narration;
+        let item = article.item;
+
+        let output_context = output::Context {
+            feed_title,
+            article_title: item.title.as_deref(),
+            published: item.time.unwrap_or(run_started),
+            article_index: article.index + 1,
+            chunk: 1,
+        };
+        let metadata = Metadata {
+            title: item.title.clone(),
+            artist: Some(self.tts.voice()),
+            album: feed_title.map(ToString::to_string),
+            track: u32::try_from(article.index + 1).ok(),
+            date: Some(output_context.published),
+            comment: item.link.clone(),
+        };
+
+        if output.template.is_per_chunk() {
+            for (chunk, audio) in audio.into_iter().enumerate() {
+                let file = output.template.render(&output::Context {
+                    chunk: chunk + 1,
+                    ..output_context
+                });
+                let file =
+                    output::available_path(&output.directory, &for_format(file, audio.format()))
+                        .await?;
+                audio
+                    .with_metadata(metadata.clone())
+                    .save(output.directory.join(file))
+                    .await?;
+            }
+            return Ok(item);
+        }
+
+        let audio = VecU8A::concat(audio).with_metadata(metadata);
+        let file = output::available_path(
+            &output.directory,
+            &for_format(output.template.render(&output_context), audio.format()),
+        )
+        .await?;
+        let path = output.directory.join(&file);
+        audio.save(&path).await?;
+
+        if let (Some(podcast), Some(base_url)) = (podcast, &output.podcast_base_url) {
+            let file_name = file.to_string_lossy().to_string();
+            let length = tokio::fs::metadata(&path).await.into_diagnostic()?.len();
+
+            podcast.add(Episode {
+                title: item.title.clone().unwrap_or_else(|| file_name.clone()),
+                link: item.link.clone(),
+                guid: Guid::new(
+                    item.id
+                        .clone()
+                        .or_else(|| item.link.clone())
+                        .unwrap_or_else(|| file_name.clone()),
+                ),
+                published: output_context.published,
+                enclosure: Enclosure::new(
+                    podcast::episode_url(base_url, &file_name)?,
+                    length,
+                    audio.format(),
+                ),
+                duration: audio.duration(),
+            });
+            podcast
+                .save(output.directory.join(podcast::FILE_NAME))
+                .await?;
+        }
+
+        Ok(item)
+    }
+}
+
+/// Give `file` the extension of the format it is saved in, replacing one for another format
+fn for_format(file: PathBuf, format: Format) -> PathBuf {
+    if file.extension().is_none() || Format::from_path(&file).is_some() {
+        file.with_extension(format.extension())
+    } else {
+        let mut name = file.into_os_string();
+        name.push(".");
+        name.push(format.extension());
+        PathBuf::from(name)
+    }
+}
+
+/// Replace an article's summary with the page it links to, keeping the summary if that fails
+async fn full_article_or_summary(
+    client: &readability::Reqwest,
+    link: &str,
+    summary: String,
+) -> String {
+    let url = match Url::parse(link) {
+        Ok(url) => url,
+        Err(error) => {
+            tracing::warn!(
+                "Skipping full article, {} is not a valid URL: {}",
+                link,
+                error
+            );
+            return summary;
+        }
+    };
+
+    match client.full_article(&url).await {
+        Ok(Some(article)) => article,
+        Ok(None) => {
+            tracing::warn!("Could not find an article at {}, using the summary", url);
+            summary
+        }
+        Err(error) => {
+            tracing::warn!("Failed to download {}, using the summary: {:?}", url, error);
+            summary
+        }
+    }
+}
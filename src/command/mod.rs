@@ -1 +1,5 @@
+pub mod cache;
+pub mod feed_to_audio;
 pub mod read_aloud;
+pub mod state;
+pub mod voices;
@@ -1,62 +1,258 @@
-use std::{fmt::Debug, path::Path};
+use std::{collections::BTreeSet, fmt::Debug, num::NonZeroUsize, path::Path};
 
-use miette::Result;
+use chrono::Utc;
+use futures::{stream, StreamExt, TryStreamExt};
+use miette::{miette, Result};
 use tracing::instrument;
 
-use super::super::remote::{chatgpt, elevenlabs};
+use super::super::remote::chatgpt;
 use crate::{
     chatgpt::Direction,
-    io::audio::Audio,
+    io::{
+        audio::{Audio, Metadata, VecU8A},
+        session::Session,
+        stream::Speaker,
+    },
     remote::{
-        chatgpt::{Prompt, Repository as ChatGPTRepository},
-        elevenlabs::{Repository as ElevenlabsRepository, Voice},
+        chatgpt::{GenerationOptions, Message, Prompt, Repository as ChatGPTRepository, Turn},
+        tts::{AnyTts, Tts},
+    },
+    text::{
+        chunk::chunk,
+        dialogue::{self, Line},
     },
 };
 
+/// What a story that has already started is asked when there is no prompt
+const NEXT_CHAPTER: &str = "Continue the story with the next chapter";
+
 #[derive(Debug)]
 pub struct Command {
-    chatgpt_client: chatgpt::ChatGPT,
-    elevenlabs_client: elevenlabs::Reqwest,
+    chatgpt_client: chatgpt::AnyGenerator,
+    generation_options: GenerationOptions,
+    tts: AnyTts,
+    /// Who reads each character's lines, by name, where the story is read as dialogue
+    characters: Vec<(String, AnyTts)>,
+    concurrency: NonZeroUsize,
 }
 
 impl Command {
-    pub const fn new(
-        chatgpt_client: chatgpt::ChatGPT,
-        elevenlabs_client: elevenlabs::Reqwest,
+    /// `concurrency` is how many chunks of a long story are synthesized at once
+    pub fn new(
+        chatgpt_client: chatgpt::AnyGenerator,
+        tts: AnyTts,
+        concurrency: NonZeroUsize,
     ) -> Self {
         Self {
             chatgpt_client,
-            elevenlabs_client,
+            generation_options: GenerationOptions::default(),
+            tts,
+            characters: vec![],
+            concurrency,
         }
     }
 
+    /// Ask for the story as a script, and read each character's lines with their own voice
+    ///
+    /// The narrator, and any character without a voice, is read by the voice the command was
+    /// made with.
+    #[must_use]
+    pub fn with_characters(mut self, characters: Vec<(String, AnyTts)>) -> Self {
+        self.characters = characters;
+        self
+    }
+
+    /// Generate the story with `generation_options` rather than the model's defaults
+    #[must_use]
+    pub fn with_generation_options(mut self, generation_options: GenerationOptions) -> Self {
+        self.generation_options = generation_options;
+        self
+    }
+
+    #[allow(
+        clippy::future_not_send,
+        reason = "Playback holds the output device, which can't be sent between threads"
+    )]
     #[instrument]
     pub async fn run<
         D: Into<Direction> + Sync + Send + Debug,
         P: Into<Prompt> + Sync + Send + Debug,
-        V: Into<Voice> + Sync + Send + Debug,
         O: AsRef<Path> + Sync + Send + Debug,
     >(
         self,
         chatgpt_direction: D,
         chatgpt_prompt: P,
-        elevenlabs_voice: V,
         output: Option<O>,
     ) -> Result<()> {
+        let chatgpt_prompt = chatgpt_prompt.into();
+        let metadata = Metadata {
+            title: Some(chatgpt_prompt.to_string()),
+            artist: Some(self.tts.voice()),
+            album: None,
+            track: None,
+            date: Some(Utc::now()),
+            comment: Some(chatgpt_prompt.to_string()),
+        };
+
         let message = self
             .chatgpt_client
-            .generate_text(chatgpt_direction.into(), chatgpt_prompt.into())
+            .generate_text(
+                self.direction(chatgpt_direction.into()),
+                chatgpt_prompt,
+                &self.generation_options,
+            )
             .await?;
-        let audio = self
-            .elevenlabs_client
-            .text_to_speech(elevenlabs_voice.into(), message)
+
+        self.read(&message, metadata, output).await
+    }
+
+    /// Ask for the next chapter of the story in `session_file`, read it, then remember it so the
+    /// chapter after follows on from it
+    ///
+    /// A new story is started with `chatgpt_direction` and `chatgpt_prompt`, while a story
+    /// that has already started keeps the direction it started with, and is asked for its next
+    /// chapter when there is no prompt.
+    #[allow(
+        clippy::future_not_send,
+        reason = "Playback holds the output device, which can't be sent between threads"
+    )]
+    #[instrument]
+    pub async fn continue_story<
+        S: AsRef<Path> + Sync + Send + Debug,
+        D: Into<Direction> + Sync + Send + Debug,
+        P: Into<Prompt> + Sync + Send + Debug,
+        O: AsRef<Path> + Sync + Send + Debug,
+    >(
+        self,
+        session_file: S,
+        chatgpt_direction: D,
+        chatgpt_prompt: Option<P>,
+        output: Option<O>,
+    ) -> Result<()> {
+        let session_file = session_file.as_ref();
+        let mut session = match Session::load(session_file).await? {
+            Some(session) => session,
+            None if chatgpt_prompt.is_none() => {
+                return Err(miette!(
+                    "There is no story in {} to continue, give a prompt to start one",
+                    session_file.display()
+                ));
+            }
+            None => Session::new(chatgpt_direction.into()),
+        };
+        let chatgpt_prompt =
+            chatgpt_prompt.map_or_else(|| NEXT_CHAPTER.to_string().into(), Into::into);
+        let chapter = session.chapters.len() + 1;
+        let metadata = Metadata {
+            title: Some(format!("Chapter {chapter}")),
+            artist: Some(self.tts.voice()),
+            album: None,
+            track: u32::try_from(chapter).ok(),
+            date: Some(Utc::now()),
+            comment: Some(chatgpt_prompt.to_string()),
+        };
+
+        let message = self
+            .chatgpt_client
+            .continue_conversation(
+                &self.direction(session.direction.clone()),
+                &session.chapters,
+                chatgpt_prompt.clone(),
+                &self.generation_options,
+            )
             .await?;
+        self.read(&message, metadata, output).await?;
+
+        session.chapters.push(Turn {
+            prompt: chatgpt_prompt,
+            message,
+        });
+        session.save(session_file).await
+    }
 
-        if let Some(path) = output {
-            audio.save(path.as_ref()).await?;
+    /// `direction`, asking for a script when characters have their own voices
+    fn direction(&self, direction: Direction) -> Direction {
+        if self.characters.is_empty() {
+            direction
         } else {
-            audio.play()?;
+            format!("{direction} {}", dialogue::DIRECTION).into()
         }
+    }
+
+    /// Who reads the lines of the character `name`, ignoring case
+    fn character(&self, name: &str) -> Option<&AnyTts> {
+        self.characters
+            .iter()
+            .find(|(character, _)| character.eq_ignore_ascii_case(name))
+            .map(|(_, tts)| tts)
+    }
+
+    /// Read `message` aloud, or save it to `output` with `metadata`
+    #[allow(
+        clippy::future_not_send,
+        reason = "Playback holds the output device, which can't be sent between threads"
+    )]
+    async fn read<O: AsRef<Path> + Sync + Send + Debug>(
+        &self,
+        message: &Message,
+        metadata: Metadata,
+        output: Option<O>,
+    ) -> Result<()> {
+        let lines = if self.characters.is_empty() {
+            vec![Line {
+                speaker: None,
+                text: message.to_string(),
+            }]
+        } else {
+            dialogue::lines(&message.to_string())
+        };
+        let unknown: BTreeSet<_> = lines
+            .iter()
+            .filter_map(|line| line.speaker.as_deref())
+            .filter(|speaker| self.character(speaker).is_none())
+            .collect();
+        for speaker in unknown {
+            tracing::warn!("{speaker} has no voice of their own, reading them as the narrator");
+        }
+
+        // Stories can be longer than can be read at once, so they are read in order, in pieces,
+        // each with the voice of whoever says it, and joined back together
+        let chunks: Vec<_> = lines
+            .iter()
+            .flat_map(|line| {
+                let tts = line
+                    .speaker
+                    .as_deref()
+                    .and_then(|speaker| self.character(speaker))
+                    .unwrap_or(&self.tts);
+                chunk(&line.text, tts.max_characters(), None)
+                    .into_iter()
+                    .map(move |text| (tts, text))
+            })
+            .collect();
+
+        let Some(path) = output else {
+            // Playback starts as soon as the first chunk starts arriving, and each chunk is
+            // fetched while the one before it plays
+            let speaker = Speaker::try_new()?;
+            for (tts, text) in chunks {
+                let audio = tts.stream_text_to_speech(text).await?;
+                speaker.queue(audio).await?;
+            }
+            speaker.finish().await;
+
+            return Ok(());
+        };
+
+        let audio = stream::iter(chunks)
+            .map(|(tts, text)| tts.text_to_speech(text))
+            .buffered(self.concurrency.get())
+            .try_collect::<Vec<_>>()
+            .await?;
+        VecU8A::concat(audio)
+            .with_metadata(metadata)
+            .save(path.as_ref())
+            .await?;
 
         Ok(())
     }
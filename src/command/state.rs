@@ -0,0 +1,55 @@
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+};
+
+use miette::Result;
+use reqwest::Url;
+use tracing::instrument;
+
+use crate::io::state::State;
+
+#[derive(Debug)]
+pub struct Command {
+    state_file: PathBuf,
+}
+
+impl Command {
+    pub fn new<P: AsRef<Path>>(state_file: P) -> Self {
+        Self {
+            state_file: state_file.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Print the articles that have been narrated, optionally only for one feed
+    #[instrument]
+    pub async fn show(self, feed: Option<Url>) -> Result<()> {
+        let state = State::load(&self.state_file).await?;
+
+        for (url, narrated) in state
+            .feeds
+            .iter()
+            .filter(|(url, _)| feed.as_ref().is_none_or(|feed| feed.as_str() == *url))
+        {
+            println!("{url}");
+            for (key, article) in narrated {
+                println!(
+                    "  {}  {}  {}",
+                    article.narrated_at.to_rfc3339(),
+                    key,
+                    article.title.as_deref().unwrap_or("(untitled)")
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forget what has been narrated, so it will be narrated again on the next run
+    #[instrument]
+    pub async fn reset(self, feed: Option<Url>) -> Result<()> {
+        let mut state = State::load(&self.state_file).await?;
+        state.reset(feed.as_ref());
+        state.save(&self.state_file).await
+    }
+}
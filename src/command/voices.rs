@@ -0,0 +1,81 @@
+use clap::ValueEnum;
+use miette::{IntoDiagnostic, Result};
+use tracing::instrument;
+
+use crate::remote::elevenlabs::{self, Repository, VoiceDetails};
+
+/// How to print the voices
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Aligned columns for reading
+    Table,
+    /// A JSON array for scripts
+    Json,
+}
+
+#[derive(Debug)]
+pub struct Command {
+    elevenlabs_client: elevenlabs::Reqwest,
+}
+
+impl Command {
+    pub const fn new(elevenlabs_client: elevenlabs::Reqwest) -> Self {
+        Self { elevenlabs_client }
+    }
+
+    /// Print the voices the account can use
+    #[instrument]
+    pub async fn run(self, format: Format) -> Result<()> {
+        let voices = self.elevenlabs_client.voices().await?;
+
+        match format {
+            Format::Table => print!("{}", table(&voices)),
+            Format::Json => println!(
+                "{}",
+                serde_json::to_string_pretty(&voices).into_diagnostic()?
+            ),
+        }
+
+        Ok(())
+    }
+}
+
+fn table(voices: &[VoiceDetails]) -> String {
+    let rows: Vec<[String; 4]> = voices
+        .iter()
+        .map(|voice| {
+            [
+                voice.id.to_string(),
+                voice.name.clone(),
+                voice.category.clone().unwrap_or_default(),
+                voice
+                    .labels
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ]
+        })
+        .collect();
+    let header = ["ID", "NAME", "CATEGORY", "LABELS"].map(String::from);
+
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    table
+}
@@ -1,51 +1,398 @@
-use std::{fmt::Debug, io::Cursor, path::Path};
+use std::{
+    fmt::{Debug, Display, Formatter},
+    path::Path,
+    str::FromStr,
+    time::Duration,
+};
 
 use async_trait::async_trait;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use id3::{frame::Comment, TagLike, Timestamp};
 use miette::{IntoDiagnostic, Result};
 use tokio::io::AsyncWriteExt;
 use tracing::instrument;
 
+use super::{mp3, ogg, wav};
+
+/// Album used when the audio didn't come from a feed with a title
+pub const DEFAULT_ALBUM: &str = "Story Time";
+
 #[derive(Debug)]
 pub struct VecU8A {
     stream: Vec<u8>,
+    format: Format,
+    metadata: Option<Metadata>,
+}
+
+/// How audio is encoded, written like `mp3_44100_128`, `pcm_24000`, `wav_24000`,
+/// `opus_48000_64` or `ulaw_8000`, or just the name for its usual settings
+///
+/// Uncompressed audio is always 16 bit mono samples, and WAV is that with a header added when
+/// it is saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// At a sample rate in hertz and a bitrate in kilobits per second
+    Mp3 {
+        sample_rate: u32,
+        bitrate: u32,
+    },
+    /// Little endian samples without any header
+    Pcm {
+        sample_rate: u32,
+    },
+    Wav {
+        sample_rate: u32,
+    },
+    /// In an Ogg file, which can be saved but not played
+    Opus {
+        sample_rate: u32,
+        bitrate: u32,
+    },
+    /// 8 bit μ-law samples, as telephone systems use
+    Ulaw {
+        sample_rate: u32,
+    },
+}
+
+impl Format {
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Mp3 { .. } => "mp3",
+            Self::Pcm { .. } => "pcm",
+            Self::Wav { .. } => "wav",
+            Self::Opus { .. } => "ogg",
+            Self::Ulaw { .. } => "ulaw",
+        }
+    }
+
+    pub const fn mime_type(self) -> &'static str {
+        match self {
+            Self::Mp3 { .. } => "audio/mpeg",
+            Self::Pcm { .. } => "audio/L16",
+            Self::Wav { .. } => "audio/wav",
+            Self::Opus { .. } => "audio/ogg",
+            Self::Ulaw { .. } => "audio/basic",
+        }
+    }
+
+    /// The format usually meant by a file extension, such as `wav` or `ogg`
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_ascii_lowercase().as_str() {
+            "mp3" => Some(Self::default()),
+            "pcm" | "raw" => "pcm".parse().ok(),
+            "wav" => "wav".parse().ok(),
+            "ogg" | "opus" => "opus".parse().ok(),
+            "ulaw" | "ul" => "ulaw".parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// The format usually meant by the extension of `path`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        Self::from_extension(path.as_ref().extension()?.to_str()?)
+    }
+
+    pub const fn is_playable(self) -> bool {
+        !matches!(self, Self::Opus { .. })
+    }
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::Mp3 {
+            sample_rate: 44100,
+            bitrate: 128,
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Mp3 {
+                sample_rate,
+                bitrate,
+            } => write!(f, "mp3_{sample_rate}_{bitrate}"),
+            Self::Pcm { sample_rate } => write!(f, "pcm_{sample_rate}"),
+            Self::Wav { sample_rate } => write!(f, "wav_{sample_rate}"),
+            Self::Opus {
+                sample_rate,
+                bitrate,
+            } => write!(f, "opus_{sample_rate}_{bitrate}"),
+            Self::Ulaw { sample_rate } => write!(f, "ulaw_{sample_rate}"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('_');
+        let codec = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let numbers = parts
+            .map(|number| {
+                number
+                    .parse::<u32>()
+                    .map_err(|_| format!("{number} in {s} isn't a number"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let format = match (codec.as_str(), numbers.as_slice()) {
+            ("mp3", []) => Self::default(),
+            ("mp3", [sample_rate, bitrate]) => Self::Mp3 {
+                sample_rate: *sample_rate,
+                bitrate: *bitrate,
+            },
+            ("pcm", []) => Self::Pcm { sample_rate: 24000 },
+            ("pcm", [sample_rate]) => Self::Pcm {
+                sample_rate: *sample_rate,
+            },
+            ("wav", []) => Self::Wav { sample_rate: 24000 },
+            ("wav", [sample_rate]) => Self::Wav {
+                sample_rate: *sample_rate,
+            },
+            ("opus" | "ogg", []) => Self::Opus {
+                sample_rate: 48000,
+                bitrate: 64,
+            },
+            ("opus" | "ogg", [sample_rate, bitrate]) => Self::Opus {
+                sample_rate: *sample_rate,
+                bitrate: *bitrate,
+            },
+            ("ulaw", []) => Self::Ulaw { sample_rate: 8000 },
+            ("ulaw", [sample_rate]) => Self::Ulaw {
+                sample_rate: *sample_rate,
+            },
+            ("mp3" | "opus" | "ogg", _) => {
+                return Err(format!(
+                    "{s} should be {codec}_<sample rate>_<bitrate>, such as {}",
+                    if codec == "mp3" {
+                        "mp3_44100_128"
+                    } else {
+                        "opus_48000_64"
+                    }
+                ))
+            }
+            ("pcm" | "wav" | "ulaw", _) => {
+                return Err(format!(
+                    "{s} should be {codec}_<sample rate>, such as {codec}_24000"
+                ))
+            }
+            _ => {
+                return Err(format!(
+                    "{s} isn't a format, it can be mp3, pcm, wav, opus or ulaw"
+                ))
+            }
+        };
+        Ok(format)
+    }
+}
+
+impl VecU8A {
+    pub const fn new(stream: Vec<u8>, format: Format) -> Self {
+        Self {
+            stream,
+            format,
+            metadata: None,
+        }
+    }
+
+    /// The audio as it is encoded, without any metadata, or the header WAV is saved with
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.stream
+    }
+}
+
+/// Describes the audio in media libraries, written into the file when it is saved
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    /// The voice reading the audio
+    pub artist: Option<String>,
+    /// The feed the audio came from, or [`DEFAULT_ALBUM`]
+    pub album: Option<String>,
+    pub track: Option<u32>,
+    pub date: Option<DateTime<Utc>>,
+    /// The prompt or the URL of the article that was read
+    pub comment: Option<String>,
 }
 
 #[async_trait]
 pub trait Audio {
-    fn play(&self) -> Result<()>;
+    fn duration(&self) -> Duration;
+    fn format(&self) -> Format;
     async fn save<P: AsRef<Path> + Debug + Sync + Send>(&self, path: P) -> Result<()>;
+
+    /// Join several pieces of audio into one that plays them back to back
+    fn concat<I: IntoIterator<Item = Self>>(audio: I) -> Self
+    where
+        Self: Sized;
+
+    /// Attach metadata that is saved along with the audio
+    #[must_use]
+    fn with_metadata(self, metadata: Metadata) -> Self
+    where
+        Self: Sized;
 }
 
 #[async_trait]
 impl Audio for VecU8A {
-    // Instrument panic is false positive
-    #[allow(clippy::panic_in_result_fn)]
     #[instrument]
-    fn play(&self) -> Result<()> {
-        let stream = self.stream.clone();
-        let cursor = Cursor::new(stream);
-
-        let (_stream, stream_handle) = rodio::OutputStream::try_default().into_diagnostic()?;
+    fn duration(&self) -> Duration {
+        let samples = |bytes_per_sample: u64, sample_rate: u32| {
+            Duration::from_micros(
+                self.stream.len() as u64 / bytes_per_sample * 1_000_000
+                    / u64::from(sample_rate).max(1),
+            )
+        };
+        match self.format {
+            Format::Mp3 { .. } => mp3::duration(&self.stream),
+            Format::Pcm { sample_rate } | Format::Wav { sample_rate } => samples(2, sample_rate),
+            Format::Ulaw { sample_rate } => samples(1, sample_rate),
+            Format::Opus { .. } => ogg::opus_duration(&self.stream),
+        }
+    }
 
-        let player = stream_handle.play_once(cursor).into_diagnostic()?;
-        player.sleep_until_end();
-        Ok(())
+    fn format(&self) -> Format {
+        self.format
     }
 
+    /// Metadata is written as ID3 tags in MP3 files and INFO tags in WAV files, other formats
+    /// are saved without it
     #[instrument]
     async fn save<P: AsRef<Path> + Debug + Sync + Send>(&self, path: P) -> Result<()> {
         let mut file = tokio::fs::File::create(path.as_ref())
             .await
             .into_diagnostic()?;
-        file.write_all(&self.stream).await.into_diagnostic()?;
+        match (self.format, &self.metadata) {
+            (Format::Mp3 { .. }, Some(metadata)) => {
+                file.write_all(&metadata.to_id3()?)
+                    .await
+                    .into_diagnostic()?;
+                // Our tag replaces any the stream came with, players only read the first one
+                let audio = &self.stream[mp3::id3v2_length(&self.stream)..];
+                file.write_all(audio).await.into_diagnostic()?;
+            }
+            (Format::Wav { sample_rate }, metadata) => {
+                let tags = metadata
+                    .as_ref()
+                    .map(Metadata::to_wav_info)
+                    .unwrap_or_default();
+                file.write_all(&wav::file(&self.stream, sample_rate, &tags))
+                    .await
+                    .into_diagnostic()?;
+            }
+            _ => file.write_all(&self.stream).await.into_diagnostic()?,
+        }
         Ok(())
     }
+
+    /// Copies the frames of each MP3 stream across, dropping the tags and Xing/Info frames that
+    /// describe only one piece and would otherwise confuse players partway through
+    ///
+    /// Samples are joined as they are, and Ogg streams are chained one after another.
+    fn concat<I: IntoIterator<Item = Self>>(audio: I) -> Self {
+        let mut audio = audio.into_iter().peekable();
+        let format = audio
+            .peek()
+            .map_or_else(Format::default, |first| first.format);
+
+        let mut stream = vec![];
+        for audio in audio {
+            if let Format::Mp3 { .. } = audio.format {
+                for frame in mp3::Frames::new(&audio.stream).filter(|frame| !frame.is_metadata()) {
+                    stream.extend_from_slice(frame.bytes);
+                }
+            } else {
+                stream.extend_from_slice(&audio.stream);
+            }
+        }
+
+        Self::new(stream, format)
+    }
+
+    fn with_metadata(self, metadata: Metadata) -> Self {
+        Self {
+            metadata: Some(metadata),
+            ..self
+        }
+    }
+}
+
+impl Metadata {
+    /// An `ID3v2.4` tag to put at the start of an MP3 file
+    fn to_id3(&self) -> Result<Vec<u8>> {
+        let mut tag = id3::Tag::new();
+        if let Some(title) = &self.title {
+            tag.set_title(title);
+        }
+        if let Some(artist) = &self.artist {
+            tag.set_artist(artist);
+        }
+        tag.set_album(self.album.as_deref().unwrap_or(DEFAULT_ALBUM));
+        if let Some(track) = self.track {
+            tag.set_track(track);
+        }
+        if let Some(date) = self.date {
+            tag.set_date_recorded(timestamp(date));
+        }
+        if let Some(comment) = &self.comment {
+            tag.add_frame(Comment {
+                lang: "eng".to_string(),
+                description: String::new(),
+                text: comment.clone(),
+            });
+        }
+
+        let mut bytes = vec![];
+        tag.write_to(&mut bytes, id3::Version::Id3v24)
+            .into_diagnostic()?;
+        Ok(bytes)
+    }
+
+    /// Tags for the `INFO` chunk of a WAV file
+    fn to_wav_info(&self) -> Vec<([u8; 4], String)> {
+        [
+            (*b"INAM", self.title.clone()),
+            (*b"IART", self.artist.clone()),
+            (
+                *b"IPRD",
+                Some(self.album.as_deref().unwrap_or(DEFAULT_ALBUM).to_string()),
+            ),
+            (*b"ITRK", self.track.map(|track| track.to_string())),
+            (
+                *b"ICRD",
+                self.date.map(|date| date.format("%Y-%m-%d").to_string()),
+            ),
+            (*b"ICMT", self.comment.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(id, value)| Some((id, value?)))
+        .collect()
+    }
+}
+
+#[allow(
+    clippy::cast_possible_truncation,
+    reason = "Months, days, hours, minutes and seconds all fit in a u8"
+)]
+fn timestamp(date: DateTime<Utc>) -> Timestamp {
+    Timestamp {
+        year: date.year(),
+        month: Some(date.month() as u8),
+        day: Some(date.day() as u8),
+        hour: Some(date.hour() as u8),
+        minute: Some(date.minute() as u8),
+        second: Some(date.second() as u8),
+    }
 }
 
+/// MP3 audio, which is what speech is unless something else is asked for
 impl From<Vec<u8>> for VecU8A {
     #[instrument]
     fn from(stream: Vec<u8>) -> Self {
-        Self { stream }
+        Self::new(stream, Format::default())
     }
 }
 
@@ -54,6 +401,7 @@ mod tests {
     use tempfile::tempdir;
 
     use super::*;
+    use crate::io::mp3::SMALLEST_SYNTACTICALLY_VALID_MP3;
 
     #[tokio::test]
     async fn save_will_write_contents() {
@@ -82,17 +430,247 @@ mod tests {
         assert_eq!(contents, vec![1, 2, 3]);
     }
 
-    #[ignore = "This test requires an audio device, which most CI environments do not have"]
+    fn metadata() -> Metadata {
+        Metadata {
+            title: Some("The Fox".to_string()),
+            artist: Some("MF3mGyEYCl7XYWbV9V6O".to_string()),
+            album: Some("Story Time Daily".to_string()),
+            track: Some(3),
+            date: Some("2023-09-25T08:30:00Z".parse().expect("Invalid date")),
+            comment: Some("https://example.com/fox".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn save_writes_metadata_as_id3_tags() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join("test.mp3");
+
+        VecU8A::from(SMALLEST_SYNTACTICALLY_VALID_MP3.to_vec())
+            .with_metadata(metadata())
+            .save(&path)
+            .await
+            .expect("Failed to save file");
+
+        let tag = id3::Tag::read_from_path(&path).expect("Failed to read tags");
+        assert_eq!(tag.title(), Some("The Fox"));
+        assert_eq!(tag.artist(), Some("MF3mGyEYCl7XYWbV9V6O"));
+        assert_eq!(tag.album(), Some("Story Time Daily"));
+        assert_eq!(tag.track(), Some(3));
+        assert_eq!(
+            tag.date_recorded().map(|date| date.to_string()),
+            Some("2023-09-25T08:30:00".to_string())
+        );
+        assert_eq!(
+            tag.comments()
+                .map(|comment| comment.text.as_str())
+                .collect::<Vec<_>>(),
+            vec!["https://example.com/fox"]
+        );
+    }
+
     #[tokio::test]
-    async fn play_will_play_contents() {
-        // Thank you https://github.com/mathiasbynens/small for contributing to the public domain
-        let smallest_syntactically_valid_mp3: Vec<u8> = vec![
-            255, 227, 24, 196, 0, 0, 0, 3, 72, 0, 0, 0, 0, 76, 65, 77, 69, 51, 46, 57, 56, 46, 50,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
-        ];
-
-        let stream = VecU8A::from(smallest_syntactically_valid_mp3);
-        stream.play().expect("Failed to play file");
+    async fn save_keeps_the_audio_after_the_tags() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join("test.mp3");
+
+        VecU8A::from(SMALLEST_SYNTACTICALLY_VALID_MP3.to_vec())
+            .with_metadata(metadata())
+            .save(&path)
+            .await
+            .expect("Failed to save file");
+
+        let contents = tokio::fs::read(path).await.expect("Failed to read file");
+        assert_eq!(
+            &contents[mp3::id3v2_length(&contents)..],
+            SMALLEST_SYNTACTICALLY_VALID_MP3
+        );
+    }
+
+    #[tokio::test]
+    async fn save_replaces_existing_tags() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join("test.mp3");
+        let mut stream = b"ID3\x04\x00\x00\x00\x00\x00\x02\x00\x00".to_vec();
+        stream.extend_from_slice(&SMALLEST_SYNTACTICALLY_VALID_MP3);
+
+        VecU8A::from(stream)
+            .with_metadata(metadata())
+            .save(&path)
+            .await
+            .expect("Failed to save file");
+
+        let contents = tokio::fs::read(path).await.expect("Failed to read file");
+        assert_eq!(
+            &contents[mp3::id3v2_length(&contents)..],
+            SMALLEST_SYNTACTICALLY_VALID_MP3
+        );
+    }
+
+    #[tokio::test]
+    async fn the_album_defaults_to_story_time() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join("test.mp3");
+
+        VecU8A::from(SMALLEST_SYNTACTICALLY_VALID_MP3.to_vec())
+            .with_metadata(Metadata::default())
+            .save(&path)
+            .await
+            .expect("Failed to save file");
+
+        let tag = id3::Tag::read_from_path(&path).expect("Failed to read tags");
+        assert_eq!(tag.album(), Some(DEFAULT_ALBUM));
+        assert_eq!(tag.title(), None);
+    }
+
+    #[test]
+    fn concat_plays_each_piece_back_to_back() {
+        let audio = VecU8A::concat([
+            VecU8A::from(SMALLEST_SYNTACTICALLY_VALID_MP3.to_vec()),
+            VecU8A::from(SMALLEST_SYNTACTICALLY_VALID_MP3.to_vec()),
+        ]);
+
+        assert_eq!(audio.stream, SMALLEST_SYNTACTICALLY_VALID_MP3.repeat(2));
+        assert_eq!(audio.duration(), Duration::from_millis(144));
+    }
+
+    #[test]
+    fn concat_drops_tags_and_info_frames() {
+        let mut tagged = b"ID3\x04\x00\x00\x00\x00\x00\x02\x00\x00".to_vec();
+        let mut info = vec![0xFF, 0xFB, 0x90, 0x00];
+        info.resize(417, 0);
+        info[36..40].copy_from_slice(b"Info");
+        tagged.extend_from_slice(&info);
+        tagged.extend_from_slice(&SMALLEST_SYNTACTICALLY_VALID_MP3);
+
+        let audio = VecU8A::concat([
+            VecU8A::from(tagged),
+            VecU8A::from(SMALLEST_SYNTACTICALLY_VALID_MP3.to_vec()),
+        ]);
+
+        assert_eq!(audio.stream, SMALLEST_SYNTACTICALLY_VALID_MP3.repeat(2));
+    }
+
+    #[test]
+    fn concat_of_nothing_is_empty() {
+        let audio = VecU8A::concat([]);
+
+        assert!(audio.stream.is_empty(), "Expected no audio");
+        assert_eq!(audio.duration(), Duration::ZERO);
+    }
+
+    #[test]
+    fn duration_is_read_from_the_stream() {
+        let stream = VecU8A::from(SMALLEST_SYNTACTICALLY_VALID_MP3.to_vec());
+
+        assert_eq!(stream.duration(), Duration::from_millis(72));
+    }
+
+    #[test]
+    fn formats_are_written_as_they_are_parsed() {
+        for format in [
+            "mp3_22050_32",
+            "mp3_44100_192",
+            "pcm_16000",
+            "wav_44100",
+            "opus_48000_128",
+            "ulaw_8000",
+        ] {
+            assert_eq!(
+                format
+                    .parse::<Format>()
+                    .expect("Failed to parse the format")
+                    .to_string(),
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn format_names_alone_have_usual_settings() {
+        assert_eq!("mp3".parse::<Format>(), Ok(Format::default()));
+        assert_eq!(
+            "wav".parse::<Format>(),
+            Ok(Format::Wav { sample_rate: 24000 })
+        );
+        assert_eq!(
+            "ulaw".parse::<Format>(),
+            Ok(Format::Ulaw { sample_rate: 8000 })
+        );
+    }
+
+    #[test]
+    fn unknown_formats_are_refused() {
+        for format in ["flac", "mp3_44100", "pcm_fast", "wav_24000_16"] {
+            assert!(
+                format.parse::<Format>().is_err(),
+                "Expected {format} to be refused"
+            );
+        }
+    }
+
+    #[test]
+    fn formats_are_worked_out_from_extensions() {
+        assert_eq!(Format::from_path("story.MP3"), Some(Format::default()));
+        assert_eq!(
+            Format::from_path("story.wav"),
+            Some(Format::Wav { sample_rate: 24000 })
+        );
+        assert_eq!(
+            Format::from_path("story.opus").map(Format::extension),
+            Some("ogg")
+        );
+        assert_eq!(Format::from_path("story.txt"), None);
+        assert_eq!(Format::from_path("story"), None);
+    }
+
+    #[tokio::test]
+    async fn wav_is_saved_with_a_header_and_tags() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join("test.wav");
+        let samples: Vec<u8> = [0_i16, 1000, -1000, 0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+
+        VecU8A::new(samples.clone(), Format::Wav { sample_rate: 24000 })
+            .with_metadata(metadata())
+            .save(&path)
+            .await
+            .expect("Failed to save file");
+
+        let contents = tokio::fs::read(path).await.expect("Failed to read file");
+        assert_eq!(&contents[..4], b"RIFF");
+        assert!(
+            contents.windows(8).any(|window| window == b"The Fox\0"),
+            "Expected the title to be tagged"
+        );
+        assert_eq!(&contents[contents.len() - samples.len()..], samples);
+    }
+
+    #[tokio::test]
+    async fn samples_are_saved_as_they_are() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join("test.ulaw");
+
+        VecU8A::new(vec![1, 2, 3], Format::Ulaw { sample_rate: 8000 })
+            .with_metadata(metadata())
+            .save(&path)
+            .await
+            .expect("Failed to save file");
+
+        let contents = tokio::fs::read(path).await.expect("Failed to read file");
+        assert_eq!(contents, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn samples_are_concatenated_and_timed_by_their_rate() {
+        let audio = VecU8A::concat([
+            VecU8A::new(vec![0; 32000], Format::Pcm { sample_rate: 16000 }),
+            VecU8A::new(vec![0; 32000], Format::Pcm { sample_rate: 16000 }),
+        ]);
+
+        assert_eq!(audio.format(), Format::Pcm { sample_rate: 16000 });
+        assert_eq!(audio.duration(), Duration::from_secs(2));
     }
 }
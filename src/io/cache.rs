@@ -0,0 +1,485 @@
+use std::{
+    fmt::{Debug, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use futures::{stream, Stream, StreamExt};
+use miette::{IntoDiagnostic, Result};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+
+use super::write_atomically;
+
+/// Audio that hasn't been used for this long is removed
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_hours(30 * 24);
+/// Past this many bytes, the least recently used audio is removed
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+/// Keys are hex SHA-256 digests, so anything else in the directory wasn't written by the cache
+const KEY_LENGTH: usize = 64;
+
+/// Audio that has already been synthesized, kept on disk so the same text isn't paid for twice
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cache {
+    directory: PathBuf,
+    max_size: u64,
+    max_age: Duration,
+}
+
+/// Audio in the cache
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+/// What was removed from the cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Removed {
+    pub entries: usize,
+    pub bytes: u64,
+}
+
+impl Cache {
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+            max_size: DEFAULT_MAX_SIZE,
+            max_age: DEFAULT_MAX_AGE,
+        }
+    }
+
+    #[must_use]
+    pub const fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    #[must_use]
+    pub const fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// Where audio is cached when no directory is given, in the user's cache directory
+    pub fn default_directory() -> Option<PathBuf> {
+        std::env::var_os("XDG_CACHE_HOME")
+            .filter(|directory| !directory.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .map(|directory| directory.join(env!("CARGO_PKG_NAME")))
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The key for audio made from `request`, which must include everything that changes how
+    /// the audio sounds
+    pub fn key(request: &[u8]) -> String {
+        Sha256::digest(request)
+            .iter()
+            .fold(String::new(), |mut key, byte| {
+                write!(key, "{byte:02x}").expect("Infallible");
+                key
+            })
+    }
+
+    /// The audio cached under `key`, marking it as used so it is kept longer
+    ///
+    /// A cache that can't be read only costs a request, so failures are logged rather than
+    /// returned
+    #[instrument]
+    pub async fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let path = self.directory.join(key);
+        match tokio::fs::read(&path).await {
+            Ok(audio) => {
+                if let Err(error) = touch(&path) {
+                    tracing::warn!("Couldn't mark {} as used: {}", path.display(), error);
+                }
+                tracing::debug!("Using cached audio {}", key);
+                Some(audio)
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => {
+                tracing::warn!("Couldn't read cached audio {}: {}", path.display(), error);
+                None
+            }
+        }
+    }
+
+    /// Cache `audio` under `key`, then remove whatever is now too old or doesn't fit
+    ///
+    /// Like reading, failures are logged rather than returned
+    #[instrument(skip(audio))]
+    pub async fn put(&self, key: &str, audio: &[u8]) {
+        if let Err(error) = self.write(key, audio).await {
+            tracing::warn!("Couldn't cache audio {}: {:?}", key, error);
+            return;
+        }
+        if let Err(error) = self.prune().await {
+            tracing::warn!("Couldn't prune the cache: {:?}", error);
+        }
+    }
+
+    /// Pass `stream` along, caching the audio under `key` once all of it has arrived
+    pub fn put_as_it_arrives<S, B, E>(
+        self,
+        key: String,
+        audio: S,
+    ) -> impl Stream<Item = Result<B, E>> + Send + 'static
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: AsRef<[u8]> + Send + 'static,
+        E: Send + 'static,
+    {
+        stream::unfold(
+            (Box::pin(audio), Vec::new(), Some(self)),
+            move |(mut audio, mut arrived, cache)| {
+                let key = key.clone();
+                async move {
+                    match audio.next().await {
+                        Some(Ok(bytes)) => {
+                            arrived.extend_from_slice(bytes.as_ref());
+                            Some((Ok(bytes), (audio, arrived, cache)))
+                        }
+                        // Half the audio is worse than none, so stop caching it
+                        Some(Err(error)) => Some((Err(error), (audio, arrived, None))),
+                        None => {
+                            if let Some(cache) = cache {
+                                cache.put(&key, &arrived).await;
+                            }
+                            None
+                        }
+                    }
+                }
+            },
+        )
+    }
+
+    /// Everything in the cache, most recently used first
+    #[instrument]
+    pub async fn entries(&self) -> Result<Vec<Entry>> {
+        let mut directory = match tokio::fs::read_dir(&self.directory).await {
+            Ok(directory) => directory,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error).into_diagnostic(),
+        };
+
+        let mut entries = vec![];
+        while let Some(file) = directory.next_entry().await.into_diagnostic()? {
+            let key = file.file_name().to_string_lossy().to_string();
+            // Only what the cache wrote is listed, so pruning or clearing a directory that is
+            // shared with other files leaves them be
+            if !is_key(&key) {
+                continue;
+            }
+            let metadata = file.metadata().await.into_diagnostic()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            entries.push(Entry {
+                key,
+                size: metadata.len(),
+                last_used: metadata.modified().into_diagnostic()?,
+            });
+        }
+
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_used));
+        Ok(entries)
+    }
+
+    /// Remove audio that is too old, then the least recently used audio until the rest fits
+    #[instrument]
+    pub async fn prune(&self) -> Result<Removed> {
+        let entries = self.entries().await?;
+        let expired = expired(&entries, SystemTime::now(), self.max_size, self.max_age);
+        self.remove(expired).await
+    }
+
+    /// Remove all the audio
+    #[instrument]
+    pub async fn clear(&self) -> Result<Removed> {
+        let entries = self.entries().await?;
+        self.remove(entries.iter().collect()).await
+    }
+
+    async fn write(&self, key: &str, audio: &[u8]) -> Result<()> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .into_diagnostic()?;
+
+        write_atomically(&self.directory.join(key), audio).await
+    }
+
+    async fn remove(&self, entries: Vec<&Entry>) -> Result<Removed> {
+        let mut removed = Removed::default();
+        for entry in entries {
+            match tokio::fs::remove_file(self.directory.join(&entry.key)).await {
+                Ok(()) => {
+                    removed.entries += 1;
+                    removed.bytes += entry.size;
+                }
+                // Another run pruning at the same time got there first
+                Err(error) if error.kind() == std::io::ErrorKind::NotFound => {}
+                Err(error) => return Err(error).into_diagnostic(),
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Whether `name` could be a key, rather than a temporary file or anything else
+fn is_key(name: &str) -> bool {
+    name.len() == KEY_LENGTH
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+/// The `entries`, most recently used first, that are older than `max_age` at `now`, or that
+/// don't fit in `max_size` after the more recently used ones
+fn expired(entries: &[Entry], now: SystemTime, max_size: u64, max_age: Duration) -> Vec<&Entry> {
+    let mut size = 0_u64;
+    entries
+        .iter()
+        .filter(|entry| {
+            let age = now.duration_since(entry.last_used).unwrap_or_default();
+            if age > max_age {
+                return true;
+            }
+            size = size.saturating_add(entry.size);
+            size > max_size
+        })
+        .collect()
+}
+
+fn touch(path: &Path) -> std::io::Result<()> {
+    std::fs::File::options()
+        .append(true)
+        .open(path)?
+        .set_modified(SystemTime::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use futures::{stream, StreamExt};
+    use tempfile::tempdir;
+
+    use super::{expired, Cache, Entry, Removed};
+
+    /// The key for audio called `name`
+    fn key(name: &str) -> String {
+        Cache::key(name.as_bytes())
+    }
+
+    fn entry(key: &str, size: u64, age: Duration, now: SystemTime) -> Entry {
+        Entry {
+            key: key.to_string(),
+            size,
+            last_used: now - age,
+        }
+    }
+
+    /// Pretend `key` was last used `age` ago
+    fn age(cache: &Cache, key: &str, age: Duration) {
+        std::fs::File::options()
+            .append(true)
+            .open(cache.directory().join(key))
+            .expect("Failed to open the entry")
+            .set_modified(SystemTime::now() - age)
+            .expect("Failed to age the entry");
+    }
+
+    #[test]
+    fn keys_are_hex_digests() {
+        let key = Cache::key(b"The Fox");
+
+        assert_eq!(key.len(), 64, "Expected a SHA-256 digest");
+        assert_eq!(key, Cache::key(b"The Fox"), "Expected the same key");
+        assert_ne!(key, Cache::key(b"The Hound"), "Expected a different key");
+    }
+
+    #[tokio::test]
+    async fn audio_is_read_back() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let cache = Cache::new(tempdir.path().join("cache"));
+
+        assert_eq!(
+            cache.get(&key("fox")).await,
+            None,
+            "Expected nothing cached yet"
+        );
+        cache.put(&key("fox"), b"audio").await;
+
+        assert_eq!(cache.get(&key("fox")).await, Some(b"audio".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn reading_marks_audio_as_used() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let cache = Cache::new(tempdir.path());
+        cache.put(&key("fox"), b"audio").await;
+        age(&cache, &key("fox"), Duration::from_hours(1));
+
+        cache.get(&key("fox")).await;
+
+        let entries = cache.entries().await.expect("Failed to list the cache");
+        assert!(
+            entries[0].last_used > SystemTime::now() - Duration::from_mins(1),
+            "Expected the entry to have been used just now"
+        );
+    }
+
+    #[tokio::test]
+    async fn old_audio_is_pruned() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let cache = Cache::new(tempdir.path()).with_max_age(Duration::from_mins(1));
+        cache.put(&key("fox"), b"audio").await;
+        cache.put(&key("hound"), b"more audio").await;
+        age(&cache, &key("hound"), Duration::from_mins(2));
+
+        let removed = cache.prune().await.expect("Failed to prune");
+
+        assert_eq!(
+            removed,
+            Removed {
+                entries: 1,
+                bytes: 10
+            }
+        );
+        assert_eq!(cache.get(&key("fox")).await, Some(b"audio".to_vec()));
+        assert_eq!(cache.get(&key("hound")).await, None);
+    }
+
+    #[tokio::test]
+    async fn least_recently_used_audio_is_pruned_to_fit() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let cache = Cache::new(tempdir.path()).with_max_size(8);
+        cache.put(&key("hound"), b"1234").await;
+        age(&cache, &key("hound"), Duration::from_mins(1));
+        cache.put(&key("fox"), b"5678").await;
+
+        cache.put(&key("cat"), b"9").await;
+
+        let keys: Vec<String> = cache
+            .entries()
+            .await
+            .expect("Failed to list the cache")
+            .into_iter()
+            .map(|entry| entry.key)
+            .collect();
+        assert_eq!(
+            keys.len(),
+            2,
+            "Expected one entry to be removed, got {keys:?}"
+        );
+        assert!(
+            !keys.contains(&key("hound")),
+            "Expected the least recently used entry to be removed, got {keys:?}"
+        );
+    }
+
+    #[test]
+    fn entries_within_the_limits_are_kept() {
+        let now = SystemTime::now();
+        let entries = [
+            entry("fox", 4, Duration::ZERO, now),
+            entry("hound", 4, Duration::from_secs(10), now),
+        ];
+
+        assert_eq!(
+            expired(&entries, now, 8, Duration::from_mins(1)),
+            Vec::<&Entry>::new(),
+            "Expected nothing to be removed"
+        );
+    }
+
+    #[tokio::test]
+    async fn clearing_removes_everything() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let cache = Cache::new(tempdir.path());
+        cache.put(&key("fox"), b"audio").await;
+        cache.put(&key("hound"), b"audio").await;
+
+        let removed = cache.clear().await.expect("Failed to clear");
+
+        assert_eq!(
+            removed,
+            Removed {
+                entries: 2,
+                bytes: 10
+            }
+        );
+        assert!(cache
+            .entries()
+            .await
+            .expect("Failed to list the cache")
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn other_files_are_left_alone() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let cache = Cache::new(tempdir.path()).with_max_age(Duration::ZERO);
+        let other = tempdir.path().join("holiday.mp3");
+        tokio::fs::write(&other, b"not ours")
+            .await
+            .expect("Failed to write another file");
+        cache.put(&key("fox"), b"audio").await;
+
+        cache.prune().await.expect("Failed to prune");
+        cache.clear().await.expect("Failed to clear");
+
+        assert!(
+            other.exists(),
+            "Expected a file the cache didn't write to survive"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_directories_are_empty() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let cache = Cache::new(tempdir.path().join("missing"));
+
+        assert_eq!(
+            cache.entries().await.expect("Failed to list the cache"),
+            vec![]
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_are_cached_once_they_finish() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let cache = Cache::new(tempdir.path());
+
+        let audio: Vec<Result<&[u8], String>> = cache
+            .clone()
+            .put_as_it_arrives(key("fox"), stream::iter([Ok(&b"au"[..]), Ok(&b"dio"[..])]))
+            .collect()
+            .await;
+
+        assert_eq!(audio, vec![Ok(&b"au"[..]), Ok(&b"dio"[..])]);
+        assert_eq!(cache.get(&key("fox")).await, Some(b"audio".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn streams_that_fail_are_not_cached() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let cache = Cache::new(tempdir.path());
+
+        let _: Vec<Result<&[u8], String>> = cache
+            .clone()
+            .put_as_it_arrives(
+                key("fox"),
+                stream::iter([Ok(&b"au"[..]), Err("Connection reset".to_string())]),
+            )
+            .collect()
+            .await;
+
+        assert_eq!(cache.get(&key("fox")).await, None);
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    ffi::OsString,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use miette::{miette, IntoDiagnostic, Result};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+use tracing::instrument;
+
+use super::{
+    audio::{Format, VecU8A},
+    wav,
+};
+
+/// Local engines have no limit, but shorter pieces start playing sooner
+pub const MAX_CHARACTERS: usize = 1000;
+pub const ESPEAK_PROGRAM: &str = "espeak-ng";
+pub const PIPER_PROGRAM: &str = "piper";
+
+/// A speech engine run on this machine, which needs no network or key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Engine {
+    /// espeak-ng, reading with a voice such as "en" or "en-us"
+    Espeak { voice: String },
+    /// Piper, reading with a model file and the sample rate from the configuration beside it
+    Piper { model: PathBuf, sample_rate: u32 },
+}
+
+/// Reads text aloud by running a local speech engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Local {
+    engine: Engine,
+    program: OsString,
+    /// PCM when raw samples were asked for, and otherwise WAV
+    raw: bool,
+}
+
+/// The part of a Piper model's configuration we need
+#[derive(Deserialize, Debug)]
+struct PiperConfig {
+    audio: PiperAudio,
+}
+
+#[derive(Deserialize, Debug)]
+struct PiperAudio {
+    sample_rate: u32,
+}
+
+impl Local {
+    pub fn espeak<T: Into<String>>(voice: T) -> Self {
+        Self {
+            engine: Engine::Espeak {
+                voice: voice.into(),
+            },
+            program: ESPEAK_PROGRAM.into(),
+            raw: false,
+        }
+    }
+
+    /// Read with the Piper `model`, whose configuration is the model's path with `.json` added
+    #[instrument]
+    pub async fn piper<P: AsRef<Path> + Debug>(model: P) -> Result<Self> {
+        let model = model.as_ref().to_path_buf();
+        let mut config = model.clone().into_os_string();
+        config.push(".json");
+
+        let config: PiperConfig = serde_json::from_slice(
+            &tokio::fs::read(&config)
+                .await
+                .map_err(|error| miette!("Couldn't read {}: {error}", config.to_string_lossy()))?,
+        )
+        .into_diagnostic()?;
+
+        Ok(Self {
+            engine: Engine::Piper {
+                model,
+                sample_rate: config.audio.sample_rate,
+            },
+            program: PIPER_PROGRAM.into(),
+            raw: false,
+        })
+    }
+
+    /// Run `program` rather than finding the engine on the `PATH`
+    #[must_use]
+    pub fn with_program(mut self, program: Option<OsString>) -> Self {
+        if let Some(program) = program {
+            self.program = program;
+        }
+        self
+    }
+
+    /// Make audio in `format`, which can only be WAV or PCM, at the rate the engine reads at,
+    /// rather than WAV
+    ///
+    /// Other formats are refused rather than made as WAV, which would be saved to a file
+    /// named for the format asked for.
+    pub fn with_format(mut self, format: Option<Format>) -> Result<Self> {
+        match format {
+            None | Some(Format::Wav { .. }) => self.raw = false,
+            Some(Format::Pcm { .. }) => self.raw = true,
+            Some(format) => {
+                return Err(miette!(
+                    "Local speech engines only make WAV or PCM audio, not {format}, give a \
+                     --format or an output ending in .wav or .pcm"
+                ))
+            }
+        }
+        Ok(self)
+    }
+
+    /// The voice or model reading
+    pub fn voice(&self) -> String {
+        match &self.engine {
+            Engine::Espeak { voice } => voice.clone(),
+            Engine::Piper { model, .. } => model
+                .file_stem()
+                .unwrap_or(model.as_os_str())
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+
+    #[instrument]
+    pub async fn text_to_speech(&self, text: &str) -> Result<VecU8A> {
+        let mut command = tokio::process::Command::new(&self.program);
+        match &self.engine {
+            Engine::Espeak { voice } => command.args(["--stdin", "--stdout", "-v", voice]),
+            Engine::Piper { model, .. } => command
+                .arg("--model")
+                .arg(model)
+                .arg("--output_raw")
+                .arg("--quiet"),
+        };
+
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|error| {
+                miette!(
+                    "Couldn't run {}, is it installed? {error}",
+                    self.program.to_string_lossy()
+                )
+            })?;
+        let mut stdin = child.stdin.take().ok_or_else(|| miette!("No stdin"))?;
+        // An engine that fails straight away stops reading, so what it says about why it failed
+        // is more use than the broken pipe
+        if let Err(error) = stdin.write_all(text.as_bytes()).await {
+            if error.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(error).into_diagnostic();
+            }
+        }
+        drop(stdin);
+
+        let output = child.wait_with_output().await.into_diagnostic()?;
+        if !output.status.success() {
+            return Err(miette!(
+                "{} failed ({}): {}",
+                self.program.to_string_lossy(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        let (sample_rate, samples) = match &self.engine {
+            Engine::Espeak { .. } => {
+                let (sample_rate, samples) = wav::samples(&output.stdout).ok_or_else(|| {
+                    miette!("{} didn't make WAV audio", self.program.to_string_lossy())
+                })?;
+                (sample_rate, samples.to_vec())
+            }
+            Engine::Piper { sample_rate, .. } => (*sample_rate, output.stdout),
+        };
+
+        let format = if self.raw {
+            Format::Pcm { sample_rate }
+        } else {
+            Format::Wav { sample_rate }
+        };
+        Ok(VecU8A::new(samples, format))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::PermissionsExt, path::Path};
+
+    use tempfile::tempdir;
+
+    use super::{Engine, Local};
+    use crate::io::audio::{Audio, Format};
+
+    /// A program that runs `script` with sh
+    async fn script(directory: &Path, script: &str) -> std::ffi::OsString {
+        let path = directory.join("engine");
+        tokio::fs::write(&path, format!("#!/bin/sh\n{script}\n"))
+            .await
+            .expect("Failed to write the script");
+        tokio::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+            .await
+            .expect("Failed to make the script runnable");
+        path.into_os_string()
+    }
+
+    #[tokio::test]
+    async fn piper_models_know_their_sample_rate() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let model = tempdir.path().join("en_GB-alan-low.onnx");
+        tokio::fs::write(
+            tempdir.path().join("en_GB-alan-low.onnx.json"),
+            r#"{"audio": {"sample_rate": 16000}}"#,
+        )
+        .await
+        .expect("Failed to write the config");
+
+        let local = Local::piper(&model)
+            .await
+            .expect("Failed to read the model");
+
+        assert_eq!(
+            local.engine,
+            Engine::Piper {
+                model,
+                sample_rate: 16000
+            }
+        );
+        assert_eq!(local.voice(), "en_GB-alan-low");
+    }
+
+    #[tokio::test]
+    async fn piper_output_is_the_samples() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let local = Local {
+            engine: Engine::Piper {
+                model: "model.onnx".into(),
+                sample_rate: 16000,
+            },
+            program: script(tempdir.path(), "cat").await,
+            raw: false,
+        };
+
+        let audio = local
+            .text_to_speech("Hi")
+            .await
+            .expect("Failed to synthesize");
+
+        assert_eq!(audio.format(), Format::Wav { sample_rate: 16000 });
+        assert_eq!(audio.duration(), std::time::Duration::from_micros(62));
+    }
+
+    #[tokio::test]
+    async fn raw_samples_can_be_asked_for() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let local = Local {
+            engine: Engine::Piper {
+                model: "model.onnx".into(),
+                sample_rate: 16000,
+            },
+            program: script(tempdir.path(), "cat").await,
+            raw: false,
+        }
+        .with_format(Some(Format::Pcm { sample_rate: 8000 }))
+        .expect("Expected PCM to be made");
+
+        let audio = local
+            .text_to_speech("Hi")
+            .await
+            .expect("Failed to synthesize");
+
+        assert_eq!(
+            audio.format(),
+            Format::Pcm { sample_rate: 16000 },
+            "Expected the rate the engine reads at"
+        );
+    }
+
+    #[test]
+    fn mp3_output_is_refused() {
+        let format = Format::from_path("story.mp3");
+
+        let error = Local::espeak("en")
+            .with_format(format)
+            .expect_err("Expected WAV not to be saved as MP3");
+
+        assert!(
+            error.to_string().contains("only make WAV or PCM"),
+            "Unexpected error {error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn espeak_must_make_wav() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let local = Local::espeak("en").with_program(Some(
+            script(tempdir.path(), "cat > /dev/null; printf 'RIFF'").await,
+        ));
+
+        let error = local
+            .text_to_speech("Hi")
+            .await
+            .expect_err("Expected a half written WAV to be refused");
+
+        assert!(
+            error.to_string().contains("didn't make WAV audio"),
+            "Unexpected error {error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn failures_include_what_the_engine_said() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let local = Local::espeak("xx").with_program(Some(
+            script(tempdir.path(), "echo 'unknown voice' >&2; exit 1").await,
+        ));
+
+        let error = local
+            .text_to_speech("Hi")
+            .await
+            .expect_err("Expected the engine to fail");
+
+        assert!(
+            error.to_string().contains("unknown voice"),
+            "Unexpected error {error}"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_engines_are_reported() {
+        let local = Local::espeak("en").with_program(Some("story-time-no-such-engine".into()));
+
+        let error = local
+            .text_to_speech("Hi")
+            .await
+            .expect_err("Expected the engine not to be found");
+
+        assert!(
+            error.to_string().contains("is it installed"),
+            "Unexpected error {error}"
+        );
+    }
+}
@@ -1 +1,27 @@
+use std::path::Path;
+
+use miette::{IntoDiagnostic, Result};
+
 pub mod audio;
+pub mod cache;
+pub mod local_tts;
+pub mod mp3;
+pub mod ogg;
+pub mod output;
+pub mod podcast;
+pub mod session;
+pub mod state;
+pub mod stream;
+pub mod wav;
+
+/// Write the file in full next to `path` and move it into place, so anything reading `path`
+/// sees either the old contents or the new ones, even if the process is interrupted
+pub async fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    tokio::fs::write(&temporary, contents)
+        .await
+        .into_diagnostic()?;
+    tokio::fs::rename(&temporary, path).await.into_diagnostic()
+}
@@ -0,0 +1,314 @@
+use std::time::Duration;
+
+const ID3V2_HEADER_LENGTH: usize = 10;
+const FRAME_HEADER_LENGTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Version {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layer {
+    One,
+    Two,
+    Three,
+}
+
+/// The fields of an MPEG audio frame header that we need to walk a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    version: Version,
+    layer: Layer,
+    bitrate: u32,
+    sample_rate: u32,
+    padding: bool,
+    mono: bool,
+}
+
+/// A single MPEG audio frame, including its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub header: Header,
+    pub bytes: &'a [u8],
+}
+
+/// Iterator over the frames of an MP3 stream, skipping tags and anything that isn't a frame
+#[derive(Debug, Clone)]
+pub struct Frames<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl Header {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let [first, second, third, fourth] = *bytes.get(..FRAME_HEADER_LENGTH)? else {
+            return None;
+        };
+        if first != 0xFF || second & 0xE0 != 0xE0 {
+            return None;
+        }
+
+        let version = match (second >> 3) & 0b11 {
+            0 => Version::Mpeg25,
+            2 => Version::Mpeg2,
+            3 => Version::Mpeg1,
+            _ => return None,
+        };
+        let layer = match (second >> 1) & 0b11 {
+            1 => Layer::Three,
+            2 => Layer::Two,
+            3 => Layer::One,
+            _ => return None,
+        };
+
+        let bitrates: [u32; 14] = match (version, layer) {
+            (Version::Mpeg1, Layer::One) => [
+                32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+            ],
+            (Version::Mpeg1, Layer::Two) => [
+                32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+            ],
+            (Version::Mpeg1, Layer::Three) => [
+                32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+            ],
+            (_, Layer::One) => [
+                32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+            ],
+            (_, _) => [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        };
+        // Index 0 is "free format" and 15 is invalid, neither of which we can walk
+        let bitrate = match third >> 4 {
+            index @ 1..=14 => bitrates[usize::from(index) - 1] * 1000,
+            _ => return None,
+        };
+
+        let sample_rates: [u32; 3] = match version {
+            Version::Mpeg1 => [44100, 48000, 32000],
+            Version::Mpeg2 => [22050, 24000, 16000],
+            Version::Mpeg25 => [11025, 12000, 8000],
+        };
+        let sample_rate = *sample_rates.get(usize::from((third >> 2) & 0b11))?;
+
+        Some(Self {
+            version,
+            layer,
+            bitrate,
+            sample_rate,
+            padding: (third >> 1) & 1 == 1,
+            mono: fourth >> 6 == 0b11,
+        })
+    }
+
+    /// Number of audio samples (per channel) in the frame
+    pub const fn samples(&self) -> u32 {
+        match (self.layer, self.version) {
+            (Layer::One, _) => 384,
+            (Layer::Two, _) | (Layer::Three, Version::Mpeg1) => 1152,
+            (Layer::Three, _) => 576,
+        }
+    }
+
+    /// Length of the whole frame in bytes, including this header
+    pub fn frame_length(&self) -> usize {
+        let length = match self.layer {
+            Layer::One => (12 * self.bitrate / self.sample_rate + u32::from(self.padding)) * 4,
+            Layer::Two | Layer::Three => {
+                self.samples() / 8 * self.bitrate / self.sample_rate + u32::from(self.padding)
+            }
+        };
+
+        usize::try_from(length).expect("Frames are at most a few kilobytes")
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_nanos(
+            u64::from(self.samples()) * 1_000_000_000 / u64::from(self.sample_rate),
+        )
+    }
+
+    /// Where the Xing/Info tag would start, after the side information
+    const fn side_information_end(&self) -> usize {
+        FRAME_HEADER_LENGTH
+            + match (self.version, self.mono) {
+                (Version::Mpeg1, false) => 32,
+                (Version::Mpeg1, true) | (_, false) => 17,
+                (_, true) => 9,
+            }
+    }
+}
+
+impl Frame<'_> {
+    /// Encoders put a silent frame with a Xing, Info or VBRI tag at the start of a stream,
+    /// describing the whole file. It is wrong as soon as the stream is cut or joined.
+    pub fn is_metadata(&self) -> bool {
+        let xing = self.header.side_information_end();
+        let is_xing = self
+            .bytes
+            .get(xing..xing + 4)
+            .is_some_and(|tag| tag == b"Xing" || tag == b"Info");
+        let is_vbri = self.bytes.get(36..40).is_some_and(|tag| tag == b"VBRI");
+
+        is_xing || is_vbri
+    }
+}
+
+impl<'a> Frames<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            position: id3v2_length(data),
+        }
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Frame<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.position + FRAME_HEADER_LENGTH <= self.data.len() {
+            let remaining = &self.data[self.position..];
+
+            if let Some(header) = Header::parse(remaining) {
+                let length = header.frame_length();
+                if let Some(bytes) = remaining.get(..length) {
+                    self.position += length;
+                    return Some(Frame { header, bytes });
+                }
+            }
+
+            self.position += 1;
+        }
+
+        None
+    }
+}
+
+/// The length of the `ID3v2` tag at the start of `data`, or 0 if there isn't one
+pub fn id3v2_length(data: &[u8]) -> usize {
+    let Some(header) = data.get(..ID3V2_HEADER_LENGTH) else {
+        return 0;
+    };
+    if &header[..3] != b"ID3" {
+        return 0;
+    }
+
+    // The size is "syncsafe", 7 bits per byte, and excludes the header and optional footer
+    let size = header[6..10]
+        .iter()
+        .fold(0, |size, byte| (size << 7) | usize::from(byte & 0x7F));
+    let footer = if header[5] & 0x10 == 0 {
+        0
+    } else {
+        ID3V2_HEADER_LENGTH
+    };
+
+    (ID3V2_HEADER_LENGTH + size + footer).min(data.len())
+}
+
+/// How long the audio in an MP3 stream plays for
+pub fn duration(data: &[u8]) -> Duration {
+    Frames::new(data)
+        .filter(|frame| !frame.is_metadata())
+        .map(|frame| frame.header.duration())
+        .sum()
+}
+
+/// The smallest file MP3 decoders accept, for tests across the audio modules
+///
+/// Thank you <https://github.com/mathiasbynens/small> for contributing to the public domain
+#[cfg(test)]
+pub const SMALLEST_SYNTACTICALLY_VALID_MP3: [u8; 72] = [
+    255, 227, 24, 196, 0, 0, 0, 3, 72, 0, 0, 0, 0, 76, 65, 77, 69, 51, 46, 57, 56, 46, 50, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn xing_frame() -> Vec<u8> {
+        // MPEG 1 layer III, 128kbps, 44.1kHz, stereo
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        frame[36..40].copy_from_slice(b"Info");
+        frame
+    }
+
+    #[test]
+    fn parses_the_smallest_mp3() {
+        let frames = Frames::new(&SMALLEST_SYNTACTICALLY_VALID_MP3).collect::<Vec<_>>();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].bytes.len(), 72);
+        assert_eq!(frames[0].header.samples(), 576);
+    }
+
+    #[test]
+    fn duration_of_the_smallest_mp3() {
+        assert_eq!(
+            duration(&SMALLEST_SYNTACTICALLY_VALID_MP3),
+            Duration::from_millis(72)
+        );
+    }
+
+    #[test]
+    fn duration_adds_up_every_frame() {
+        let stream = SMALLEST_SYNTACTICALLY_VALID_MP3.repeat(3);
+
+        assert_eq!(duration(&stream), Duration::from_millis(216));
+    }
+
+    #[test]
+    fn frames_skip_an_id3v2_tag() {
+        let mut stream = b"ID3\x04\x00\x00\x00\x00\x00\x05".to_vec();
+        stream.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00, 0x00]);
+        stream.extend_from_slice(&SMALLEST_SYNTACTICALLY_VALID_MP3);
+
+        assert_eq!(id3v2_length(&stream), 15);
+        assert_eq!(Frames::new(&stream).count(), 1);
+    }
+
+    #[test]
+    fn frames_skip_garbage_between_frames() {
+        let mut stream = SMALLEST_SYNTACTICALLY_VALID_MP3.to_vec();
+        stream.extend_from_slice(b"garbage");
+        stream.extend_from_slice(&SMALLEST_SYNTACTICALLY_VALID_MP3);
+
+        assert_eq!(Frames::new(&stream).count(), 2);
+    }
+
+    #[test]
+    fn a_truncated_frame_is_ignored() {
+        let stream = &SMALLEST_SYNTACTICALLY_VALID_MP3[..40];
+
+        assert_eq!(Frames::new(stream).count(), 0);
+    }
+
+    #[test]
+    fn info_frames_are_metadata() {
+        let frame = xing_frame();
+        let frames = Frames::new(&frame).collect::<Vec<_>>();
+
+        assert_eq!(frames.len(), 1);
+        assert!(frames[0].is_metadata(), "Expected an Info frame");
+    }
+
+    #[test]
+    fn info_frames_do_not_count_towards_the_duration() {
+        let mut stream = xing_frame();
+        stream.extend_from_slice(&SMALLEST_SYNTACTICALLY_VALID_MP3);
+
+        assert_eq!(duration(&stream), Duration::from_millis(72));
+    }
+
+    #[test]
+    fn empty_streams_have_no_duration() {
+        assert_eq!(duration(&[]), Duration::ZERO);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{collections::HashMap, time::Duration};
+
+const PAGE_HEADER_LENGTH: usize = 27;
+/// Opus positions always count samples at 48kHz, whatever rate the audio was made at
+const OPUS_SAMPLE_RATE: u64 = 48000;
+
+/// A page of an Ogg stream, the unit its packets are split into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Page<'a> {
+    granule_position: u64,
+    serial: u32,
+    data: &'a [u8],
+}
+
+/// Iterator over the pages of an Ogg stream, stopping at anything that isn't a page
+#[derive(Debug, Clone)]
+struct Pages<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Iterator for Pages<'a> {
+    type Item = Page<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self
+            .data
+            .get(self.position..self.position + PAGE_HEADER_LENGTH)?;
+        if &header[..4] != b"OggS" {
+            return None;
+        }
+
+        let granule_position = u64::from_le_bytes(header[6..14].try_into().ok()?);
+        let serial = u32::from_le_bytes(header[14..18].try_into().ok()?);
+        let segments = usize::from(header[26]);
+        let lacing = self.data.get(
+            self.position + PAGE_HEADER_LENGTH..self.position + PAGE_HEADER_LENGTH + segments,
+        )?;
+        let start = self.position + PAGE_HEADER_LENGTH + segments;
+        let length: usize = lacing.iter().map(|size| usize::from(*size)).sum();
+        let data = self.data.get(start..start + length)?;
+
+        self.position = start + length;
+        Some(Page {
+            granule_position,
+            serial,
+            data,
+        })
+    }
+}
+
+/// How long an Ogg Opus stream plays for, adding up each stream when several are chained
+/// together
+pub fn opus_duration(data: &[u8]) -> Duration {
+    // The samples each stream skips at the start, and the position of its last sample
+    let mut streams: HashMap<u32, (u64, u64)> = HashMap::new();
+    for page in (Pages { data, position: 0 }) {
+        let stream = streams.entry(page.serial).or_default();
+        if page.data.starts_with(b"OpusHead") {
+            stream.0 = page.data.get(10..12).map_or(0, |pre_skip| {
+                u64::from(u16::from_le_bytes([pre_skip[0], pre_skip[1]]))
+            });
+        } else if page.granule_position != u64::MAX {
+            // Pages that don't finish a packet have no position
+            stream.1 = stream.1.max(page.granule_position);
+        }
+    }
+
+    let samples: u64 = streams
+        .values()
+        .map(|(pre_skip, last)| last.saturating_sub(*pre_skip))
+        .sum();
+    Duration::from_micros(samples * 1_000_000 / OPUS_SAMPLE_RATE)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::opus_duration;
+
+    /// A page holding `data` in one segment
+    fn page(serial: u32, granule_position: u64, data: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\x00\x00".to_vec();
+        page.extend_from_slice(&granule_position.to_le_bytes());
+        page.extend_from_slice(&serial.to_le_bytes());
+        page.extend_from_slice(&[0; 8]);
+        page.push(1);
+        page.push(u8::try_from(data.len()).expect("Data too long for one segment"));
+        page.extend_from_slice(data);
+        page
+    }
+
+    fn stream(serial: u32, pre_skip: u16, last_position: u64) -> Vec<u8> {
+        let mut head = b"OpusHead\x01\x01".to_vec();
+        head.extend_from_slice(&pre_skip.to_le_bytes());
+        head.extend_from_slice(&[0x80, 0xBB, 0, 0, 0, 0, 0]);
+
+        [
+            page(serial, 0, &head),
+            page(serial, 0, b"OpusTags"),
+            page(serial, last_position / 2, &[1, 2, 3]),
+            page(serial, last_position, &[4, 5, 6]),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn duration_is_the_last_position_less_the_pre_skip() {
+        let duration = opus_duration(&stream(1, 312, 48312));
+
+        assert_eq!(duration, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn chained_streams_are_added_up() {
+        let chained = [stream(1, 312, 48312), stream(2, 312, 24312)].concat();
+
+        assert_eq!(opus_duration(&chained), Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn anything_else_is_empty() {
+        assert_eq!(opus_duration(b"not ogg"), Duration::ZERO);
+    }
+}
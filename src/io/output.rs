@@ -0,0 +1,401 @@
+use std::{
+    fmt::{Debug, Display, Formatter},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime,
+    Utc,
+};
+use miette::{IntoDiagnostic, Result};
+use tracing::instrument;
+
+pub const DEFAULT_TEMPLATE: &str = "{published:%Y-%m-%d}-{article_title_slug}.mp3";
+
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+const DEFAULT_FEED_TITLE: &str = "Story Time";
+const DEFAULT_ARTICLE_TITLE: &str = "untitled";
+const MAX_SLUG_LENGTH: usize = 60;
+/// Characters that are not allowed in file names on at least one common platform, or that would
+/// change the meaning of the name once it ends up in a podcast URL
+const FORBIDDEN_CHARACTERS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|', '#', '%'];
+
+/// A file name with placeholders that are filled in for each file that is saved
+///
+/// The placeholders are `{feed_title}`, `{article_title_slug}`, `{published}` (which takes an
+/// optional strftime format, as in `{published:%Y-%m-%d}`), `{article_index}` and `{chunk}`.
+/// Literal braces are written `{{` and `}}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    FeedTitle,
+    ArticleTitleSlug,
+    Published(String),
+    ArticleIndex,
+    Chunk,
+}
+
+/// What a template's placeholders are replaced with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Context<'a> {
+    pub feed_title: Option<&'a str>,
+    pub article_title: Option<&'a str>,
+    pub published: DateTime<Utc>,
+    /// Position of the article in this run, starting at 1
+    pub article_index: usize,
+    /// Position of the chunk in the article, starting at 1
+    pub chunk: usize,
+}
+
+impl Template {
+    /// Whether every chunk of an article gets its own file, rather than one file per article
+    pub fn is_per_chunk(&self) -> bool {
+        self.parts.contains(&Part::Chunk)
+    }
+
+    /// The relative path for a file, with every placeholder made safe to use in a file name
+    pub fn render(&self, context: &Context<'_>) -> PathBuf {
+        let rendered = self.parts.iter().fold(String::new(), |mut path, part| {
+            match part {
+                Part::Literal(text) => path.push_str(text),
+                Part::FeedTitle => {
+                    path.push_str(&sanitize(context.feed_title.unwrap_or(DEFAULT_FEED_TITLE)));
+                }
+                Part::ArticleTitleSlug => {
+                    path.push_str(&slug(
+                        context.article_title.unwrap_or(DEFAULT_ARTICLE_TITLE),
+                    ));
+                }
+                Part::Published(format) => {
+                    path.push_str(&sanitize(&context.published.format(format).to_string()));
+                }
+                Part::ArticleIndex => path.push_str(&context.article_index.to_string()),
+                Part::Chunk => path.push_str(&context.chunk.to_string()),
+            }
+            path
+        });
+
+        PathBuf::from(rendered)
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut characters = source.chars();
+
+        while let Some(character) = characters.next() {
+            match character {
+                '{' if characters.as_str().starts_with('{') => {
+                    characters.next();
+                    literal.push('{');
+                }
+                '}' if characters.as_str().starts_with('}') => {
+                    characters.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let rest = characters.as_str();
+                    let end = rest
+                        .find('}')
+                        .ok_or_else(|| format!("Unclosed placeholder in {source:?}"))?;
+                    let (name, format) = rest[..end]
+                        .split_once(':')
+                        .map_or((&rest[..end], None), |(name, format)| (name, Some(format)));
+
+                    let part = match (name, format) {
+                        ("feed_title", None) => Part::FeedTitle,
+                        ("article_title_slug", None) => Part::ArticleTitleSlug,
+                        ("article_index", None) => Part::ArticleIndex,
+                        ("chunk", None) => Part::Chunk,
+                        ("published", format) => {
+                            let format = format.unwrap_or(DEFAULT_DATE_FORMAT);
+                            if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                                return Err(format!("Invalid date format {format:?}"));
+                            }
+                            Part::Published(format.to_string())
+                        }
+                        _ => return Err(format!("Unknown placeholder {{{}}}", &rest[..end])),
+                    };
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(part);
+                    characters = rest[end + 1..].chars();
+                }
+                '}' => {
+                    return Err(format!(
+                        "Unmatched }} in {source:?}, write }}}} for a brace"
+                    ))
+                }
+                character => literal.push(character),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        if parts.is_empty() {
+            return Err("The output template is empty".to_string());
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            parts,
+        })
+    }
+}
+
+impl Display for Template {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Make `file` unique within `directory` by adding `-2`, `-3` and so on before its extension,
+/// creating any directories the template asked for along the way
+#[instrument]
+pub async fn available_path(directory: &Path, file: &Path) -> Result<PathBuf> {
+    if let Some(parent) = directory.join(file).parent() {
+        tokio::fs::create_dir_all(parent).await.into_diagnostic()?;
+    }
+
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = file
+        .extension()
+        .map(|extension| extension.to_string_lossy());
+    let mut candidate = file.to_path_buf();
+    let mut counter = 1;
+
+    while tokio::fs::try_exists(directory.join(&candidate))
+        .await
+        .into_diagnostic()?
+    {
+        counter += 1;
+        candidate.set_file_name(extension.as_ref().map_or_else(
+            || format!("{stem}-{counter}"),
+            |extension| format!("{stem}-{counter}.{extension}"),
+        ));
+    }
+
+    Ok(candidate)
+}
+
+/// Lowercase words joined by dashes, keeping letters from any script
+fn slug(title: &str) -> String {
+    let slug = title
+        .to_lowercase()
+        .split(|character: char| !character.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let slug = slug
+        .chars()
+        .take(MAX_SLUG_LENGTH)
+        .collect::<String>()
+        .trim_end_matches('-')
+        .to_string();
+
+    if slug.is_empty() {
+        DEFAULT_ARTICLE_TITLE.to_string()
+    } else {
+        slug
+    }
+}
+
+/// Replace anything that can't be part of a file name, so a value can't add directories
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|character| {
+            if character.is_control() || FORBIDDEN_CHARACTERS.contains(&character) {
+                '-'
+            } else {
+                character
+            }
+        })
+        .collect::<String>()
+        .trim_matches(|character: char| character.is_whitespace() || character == '.')
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use chrono::{TimeZone, Utc};
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn template(source: &str) -> Template {
+        source.parse().expect("Invalid template")
+    }
+
+    fn context() -> Context<'static> {
+        Context {
+            feed_title: Some("Story Time Daily"),
+            article_title: Some("The Quick Brown Fox!"),
+            published: Utc
+                .with_ymd_and_hms(2023, 9, 25, 8, 30, 0)
+                .single()
+                .expect("Invalid date"),
+            article_index: 3,
+            chunk: 2,
+        }
+    }
+
+    #[test]
+    fn the_default_template_uses_the_date_and_title() {
+        assert_eq!(
+            template(DEFAULT_TEMPLATE).render(&context()),
+            PathBuf::from("2023-09-25-the-quick-brown-fox.mp3")
+        );
+    }
+
+    #[test]
+    fn every_placeholder_is_rendered() {
+        assert_eq!(
+            template("{feed_title}/{article_index}-{chunk}-{published}-{article_title_slug}.mp3")
+                .render(&context()),
+            PathBuf::from("Story Time Daily/3-2-2023-09-25-the-quick-brown-fox.mp3")
+        );
+    }
+
+    #[test]
+    fn braces_can_be_escaped() {
+        assert_eq!(
+            template("{{{chunk}}}.mp3").render(&context()),
+            PathBuf::from("{2}.mp3")
+        );
+    }
+
+    #[test]
+    fn values_cannot_add_directories() {
+        let context = Context {
+            feed_title: Some("../News/Today"),
+            ..context()
+        };
+
+        assert_eq!(
+            template("{feed_title}-{published:%H:%M}.mp3").render(&context),
+            PathBuf::from("-News-Today-08-30.mp3")
+        );
+    }
+
+    #[test]
+    fn slugs_keep_letters_from_any_script() {
+        assert_eq!(
+            slug("Ünïcödé — Ελληνικά, 日本語"),
+            "ünïcödé-ελληνικά-日本語"
+        );
+    }
+
+    #[test]
+    fn slugs_are_truncated() {
+        let slug = slug(&"word ".repeat(40));
+
+        assert!(
+            slug.chars().count() <= MAX_SLUG_LENGTH,
+            "Expected {slug} to be truncated"
+        );
+        assert!(!slug.ends_with('-'), "Expected {slug} not to end in a dash");
+    }
+
+    #[test]
+    fn missing_values_have_defaults() {
+        let context = Context {
+            feed_title: None,
+            article_title: Some("?!"),
+            ..context()
+        };
+
+        assert_eq!(
+            template("{feed_title}-{article_title_slug}.mp3").render(&context),
+            PathBuf::from("Story Time-untitled.mp3")
+        );
+    }
+
+    #[test]
+    fn templates_with_a_chunk_are_per_chunk() {
+        assert!(
+            template("{article_index}-{chunk}.mp3").is_per_chunk(),
+            "Expected a template with a chunk to be per chunk"
+        );
+        assert!(
+            !template(DEFAULT_TEMPLATE).is_per_chunk(),
+            "Expected the default template to be per article"
+        );
+    }
+
+    #[test]
+    fn invalid_templates_are_rejected() {
+        for source in [
+            "",
+            "{unknown}.mp3",
+            "{published.mp3",
+            "}.mp3",
+            "{published:%Q}.mp3",
+            "{chunk:%Y}.mp3",
+        ] {
+            assert!(
+                source.parse::<Template>().is_err(),
+                "Expected {source:?} to be rejected"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn available_path_keeps_a_new_name() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+
+        let path = available_path(tempdir.path(), Path::new("story.mp3"))
+            .await
+            .expect("Failed to find a path");
+
+        assert_eq!(path, PathBuf::from("story.mp3"));
+    }
+
+    #[tokio::test]
+    async fn available_path_adds_a_counter_on_collision() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        for existing in ["story.mp3", "story-2.mp3"] {
+            tokio::fs::write(tempdir.path().join(existing), [])
+                .await
+                .expect("Failed to create file");
+        }
+
+        let path = available_path(tempdir.path(), Path::new("story.mp3"))
+            .await
+            .expect("Failed to find a path");
+
+        assert_eq!(path, PathBuf::from("story-3.mp3"));
+    }
+
+    #[tokio::test]
+    async fn available_path_creates_directories() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+
+        let path = available_path(tempdir.path(), Path::new("Story Time/story.mp3"))
+            .await
+            .expect("Failed to find a path");
+
+        assert_eq!(path, PathBuf::from("Story Time/story.mp3"));
+        assert!(
+            tempdir.path().join("Story Time").is_dir(),
+            "Expected the directory to be created"
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use std::{cmp::Reverse, fmt::Debug, path::Path, time::Duration};
+
+use chrono::{DateTime, Utc};
+use miette::{miette, IntoDiagnostic, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::{audio::Format, write_atomically};
+
+/// Name of the podcast feed written alongside the audio files
+pub const FILE_NAME: &str = "podcast.xml";
+
+const ITUNES_NAMESPACE: &str = "http://www.itunes.com/dtds/podcast-1.0.dtd";
+
+/// A podcast RSS 2.0 feed, with the iTunes tags podcast apps look for
+///
+/// quick-xml matches elements by their local name when reading, so the `itunes:` prefix is only
+/// added when writing.
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename = "rss")]
+pub struct Podcast {
+    #[serde(rename = "@version")]
+    version: String,
+    #[serde(rename = "@xmlns:itunes")]
+    itunes_namespace: String,
+    channel: Channel,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+struct Channel {
+    title: String,
+    link: String,
+    description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    language: Option<String>,
+    #[serde(rename(serialize = "itunes:author", deserialize = "author"))]
+    author: String,
+    #[serde(rename(serialize = "itunes:explicit", deserialize = "explicit"))]
+    explicit: bool,
+    #[serde(rename(serialize = "itunes:category", deserialize = "category"))]
+    category: Category,
+    #[serde(
+        rename(serialize = "itunes:image", deserialize = "image"),
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    image: Option<Image>,
+    #[serde(rename = "item", default)]
+    episodes: Vec<Episode>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+struct Category {
+    #[serde(rename = "@text")]
+    text: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+struct Image {
+    #[serde(rename = "@href")]
+    href: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Episode {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+    pub guid: Guid,
+    #[serde(rename = "pubDate", with = "rfc2822")]
+    pub published: DateTime<Utc>,
+    pub enclosure: Enclosure,
+    #[serde(
+        rename(serialize = "itunes:duration", deserialize = "duration"),
+        with = "itunes_duration"
+    )]
+    pub duration: Duration,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Guid {
+    #[serde(rename = "@isPermaLink")]
+    is_permalink: bool,
+    #[serde(rename = "$text")]
+    value: String,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Enclosure {
+    #[serde(rename = "@url")]
+    url: String,
+    #[serde(rename = "@length")]
+    length: u64,
+    #[serde(rename = "@type")]
+    mime_type: String,
+}
+
+impl Podcast {
+    pub fn new<T: Into<String>, L: Into<String>, D: Into<String>>(
+        title: T,
+        link: L,
+        description: D,
+        language: Option<String>,
+    ) -> Self {
+        Self {
+            version: "2.0".to_string(),
+            itunes_namespace: ITUNES_NAMESPACE.to_string(),
+            channel: Channel {
+                title: title.into(),
+                link: link.into(),
+                description: description.into(),
+                language,
+                author: "Story Time".to_string(),
+                explicit: false,
+                category: Category {
+                    text: "News".to_string(),
+                },
+                image: None,
+                episodes: vec![],
+            },
+        }
+    }
+
+    /// Load a podcast feed written by a previous run, if there is one
+    #[instrument]
+    pub async fn load<P: AsRef<Path> + Debug + Sync + Send>(path: P) -> Result<Option<Self>> {
+        match tokio::fs::read_to_string(path.as_ref()).await {
+            Ok(contents) => quick_xml::de::from_str(&contents)
+                .map(Some)
+                .into_diagnostic(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error).into_diagnostic(),
+        }
+    }
+
+    /// Show the artwork at `image` for the podcast, keeping what it had when there is none
+    ///
+    /// Apple Podcasts only lists podcasts with artwork, a square JPEG or PNG between 1400 and
+    /// 3000 pixels across.
+    #[must_use]
+    pub fn with_image(mut self, image: Option<&Url>) -> Self {
+        if let Some(image) = image {
+            self.channel.image = Some(Image {
+                href: image.to_string(),
+            });
+        }
+        self
+    }
+
+    #[instrument(skip(self))]
+    pub async fn save<P: AsRef<Path> + Debug + Sync + Send>(&self, path: P) -> Result<()> {
+        let mut contents = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        self.serialize(quick_xml::se::Serializer::new(&mut contents))
+            .into_diagnostic()?;
+        contents.push('\n');
+
+        write_atomically(path.as_ref(), contents.as_bytes()).await
+    }
+
+    /// Add an episode, replacing any earlier episode with the same GUID, newest first
+    pub fn add(&mut self, episode: Episode) {
+        let episodes = &mut self.channel.episodes;
+        episodes.retain(|existing| existing.guid != episode.guid);
+        episodes.push(episode);
+        episodes.sort_by_key(|episode| Reverse(episode.published));
+    }
+}
+
+/// Where the episode saved as `file_name` is served from, under `base_url` whether or not it ends
+/// with a slash
+pub fn episode_url(base_url: &Url, file_name: &str) -> Result<Url> {
+    let mut url = base_url.clone();
+    url.path_segments_mut()
+        .map_err(|()| miette!("{base_url} can't be used as the podcast base URL"))?
+        .pop_if_empty()
+        .extend(file_name.split('/'));
+    Ok(url)
+}
+
+impl Guid {
+    pub fn new<T: Into<String>>(value: T) -> Self {
+        Self {
+            is_permalink: false,
+            value: value.into(),
+        }
+    }
+}
+
+impl Enclosure {
+    /// A file of `length` bytes at `url`, holding audio in `format`
+    pub fn new<T: Into<String>>(url: T, length: u64, format: Format) -> Self {
+        Self {
+            url: url.into(),
+            length,
+            mime_type: format.mime_type().to_string(),
+        }
+    }
+}
+
+mod rfc2822 {
+    use chrono::{DateTime, Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        time: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.to_rfc2822())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        let time = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc2822(&time)
+            .map(|time| time.with_timezone(&Utc))
+            .map_err(D::Error::custom)
+    }
+}
+
+/// iTunes durations are `HH:MM:SS`, but `MM:SS` and plain seconds are also allowed
+mod itunes_duration {
+    use std::time::Duration;
+
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        let seconds = duration.as_secs();
+        serializer.serialize_str(&format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        ))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let duration = String::deserialize(deserializer)?;
+        duration
+            .split(':')
+            .try_fold(0, |total, part| {
+                part.trim().parse::<u64>().map(|part| total * 60 + part)
+            })
+            .map(Duration::from_secs)
+            .map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn episode(guid: &str, published: &str) -> Episode {
+        Episode {
+            title: "The Fox".to_string(),
+            link: Some("https://example.com/fox".to_string()),
+            guid: Guid::new(guid),
+            published: published.parse().expect("Invalid date"),
+            enclosure: Enclosure::new(
+                "https://podcasts.example.com/fox.mp3",
+                1234,
+                Format::default(),
+            ),
+            duration: Duration::from_secs(3723),
+        }
+    }
+
+    fn podcast() -> Podcast {
+        Podcast::new(
+            "Story Time Daily",
+            "https://example.com/feed.xml",
+            "Stories, daily",
+            Some("en".to_string()),
+        )
+    }
+
+    #[tokio::test]
+    async fn save_writes_an_rss_feed_with_itunes_tags() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join(FILE_NAME);
+        let mut podcast = podcast();
+        podcast.add(episode("fox", "2023-09-25T08:30:00Z"));
+
+        podcast.save(&path).await.expect("Failed to save podcast");
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .expect("Failed to read podcast");
+
+        for expected in [
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>",
+            "<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\">",
+            "<title>Story Time Daily</title>",
+            "<itunes:author>Story Time</itunes:author>",
+            "<itunes:category text=\"News\"/>",
+            "<guid isPermaLink=\"false\">fox</guid>",
+            "<pubDate>Mon, 25 Sep 2023 08:30:00 +0000</pubDate>",
+            "<enclosure url=\"https://podcasts.example.com/fox.mp3\" length=\"1234\" \
+             type=\"audio/mpeg\"/>",
+            "<itunes:duration>01:02:03</itunes:duration>",
+            "<link>https://example.com/fox</link>",
+        ] {
+            assert!(
+                contents.contains(expected),
+                "Expected {expected} in {contents}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn save_replaces_the_feed_in_one_step() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join(FILE_NAME);
+        tokio::fs::write(&path, "<rss")
+            .await
+            .expect("Failed to write a truncated feed");
+
+        podcast().save(&path).await.expect("Failed to save podcast");
+
+        assert_eq!(
+            Podcast::load(&path).await.expect("Failed to load podcast"),
+            Some(podcast())
+        );
+        assert!(
+            !tempdir.path().join("podcast.xml.tmp").exists(),
+            "Expected the temporary file to be moved into place"
+        );
+    }
+
+    #[tokio::test]
+    async fn images_are_saved_and_loaded() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join(FILE_NAME);
+        let image = Url::parse("https://podcasts.example.com/cover.png").expect("Invalid URL");
+        let podcast = podcast().with_image(Some(&image));
+
+        podcast.save(&path).await.expect("Failed to save podcast");
+        let contents = tokio::fs::read_to_string(&path)
+            .await
+            .expect("Failed to read podcast");
+
+        assert!(
+            contents.contains("<itunes:image href=\"https://podcasts.example.com/cover.png\"/>"),
+            "Expected the image in {contents}"
+        );
+        assert_eq!(
+            Podcast::load(&path).await.expect("Failed to load podcast"),
+            Some(podcast.with_image(None)),
+            "Expected the image to be kept when there isn't a new one"
+        );
+    }
+
+    #[test]
+    fn episodes_are_served_from_under_the_base_url() {
+        for base_url in [
+            "https://podcasts.example.com/stories",
+            "https://podcasts.example.com/stories/",
+        ] {
+            let base_url = Url::parse(base_url).expect("Invalid URL");
+
+            assert_eq!(
+                episode_url(&base_url, "The Fox.mp3")
+                    .expect("Failed to make the URL")
+                    .as_str(),
+                "https://podcasts.example.com/stories/The%20Fox.mp3",
+                "Unexpected URL under {base_url}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn saved_podcasts_can_be_read_by_feed_parsers() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join(FILE_NAME);
+        let mut podcast = podcast();
+        podcast.add(episode("fox", "2023-09-25T08:30:00Z"));
+        podcast.save(&path).await.expect("Failed to save podcast");
+
+        let contents = tokio::fs::read(&path)
+            .await
+            .expect("Failed to read podcast");
+        let feed = feed_rs::parser::parse(contents.as_slice()).expect("Failed to parse podcast");
+
+        assert_eq!(feed.entries.len(), 1);
+        assert_eq!(
+            feed.entries[0].media[0].content[0]
+                .url
+                .as_ref()
+                .map(ToString::to_string),
+            Some("https://podcasts.example.com/fox.mp3".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn load_reads_back_a_saved_podcast() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join(FILE_NAME);
+        let mut podcast = podcast();
+        podcast.add(episode("fox", "2023-09-25T08:30:00Z"));
+        podcast.add(episode("owl", "2023-09-24T20:00:00Z"));
+
+        podcast.save(&path).await.expect("Failed to save podcast");
+        let loaded = Podcast::load(&path).await.expect("Failed to load podcast");
+
+        assert_eq!(loaded, Some(podcast));
+    }
+
+    #[tokio::test]
+    async fn load_returns_nothing_for_a_new_podcast() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+
+        let loaded = Podcast::load(tempdir.path().join(FILE_NAME))
+            .await
+            .expect("Failed to load podcast");
+
+        assert_eq!(loaded, None);
+    }
+
+    #[test]
+    fn add_puts_the_newest_episode_first() {
+        let mut podcast = podcast();
+        podcast.add(episode("owl", "2023-09-24T20:00:00Z"));
+        podcast.add(episode("fox", "2023-09-25T08:30:00Z"));
+        podcast.add(episode("badger", "2023-09-23T08:30:00Z"));
+
+        assert_eq!(
+            podcast
+                .channel
+                .episodes
+                .iter()
+                .map(|episode| episode.guid.value.as_str())
+                .collect::<Vec<_>>(),
+            vec!["fox", "owl", "badger"]
+        );
+    }
+
+    #[test]
+    fn add_replaces_an_episode_with_the_same_guid() {
+        let mut podcast = podcast();
+        podcast.add(episode("fox", "2023-09-24T20:00:00Z"));
+        podcast.add(episode("fox", "2023-09-25T08:30:00Z"));
+
+        assert_eq!(podcast.channel.episodes.len(), 1);
+        assert_eq!(
+            podcast.channel.episodes[0].published,
+            "2023-09-25T08:30:00Z"
+                .parse::<DateTime<Utc>>()
+                .expect("Invalid date")
+        );
+    }
+
+    #[test]
+    fn durations_can_be_read_in_any_itunes_format() {
+        for (duration, expected) in [("01:02:03", 3723), ("62:03", 3723), ("3723", 3723)] {
+            let xml = format!(
+                "<item><title>Fox</title><guid isPermaLink=\"false\">fox</guid><pubDate>Mon, 25 \
+                 Sep 2023 08:30:00 +0000</pubDate><enclosure url=\"fox.mp3\" length=\"1\" \
+                 type=\"audio/mpeg\"/><itunes:duration>{duration}</itunes:duration></item>"
+            );
+
+            let episode: Episode = quick_xml::de::from_str(&xml).expect("Failed to parse episode");
+
+            assert_eq!(episode.duration, Duration::from_secs(expected));
+        }
+    }
+}
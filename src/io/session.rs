@@ -0,0 +1,96 @@
+use std::{fmt::Debug, path::Path};
+
+use miette::{IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::write_atomically;
+use crate::remote::chatgpt::{Direction, Turn};
+
+/// A story told a chapter at a time, with everything the model needs to carry on from where it
+/// left off
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub direction: Direction,
+    /// Each prompt, and the chapter written for it, oldest first
+    #[serde(default)]
+    pub chapters: Vec<Turn>,
+}
+
+impl Session {
+    pub const fn new(direction: Direction) -> Self {
+        Self {
+            direction,
+            chapters: vec![],
+        }
+    }
+
+    /// Load the session, or nothing when the story hasn't started yet
+    #[instrument]
+    pub async fn load<P: AsRef<Path> + Debug + Sync + Send>(path: P) -> Result<Option<Self>> {
+        match tokio::fs::read(path.as_ref()).await {
+            Ok(contents) => serde_json::from_slice(&contents).into_diagnostic(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error).into_diagnostic(),
+        }
+    }
+
+    /// Save the session as indented JSON, so it can be read or edited by hand
+    #[instrument(skip(self))]
+    pub async fn save<P: AsRef<Path> + Debug + Sync + Send>(&self, path: P) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(self).into_diagnostic()?;
+        write_atomically(path.as_ref(), &contents).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::Session;
+    use crate::remote::chatgpt::{Direction, Message, Prompt, Turn};
+
+    #[tokio::test]
+    async fn missing_sessions_have_not_started() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+
+        let session = Session::load(tempdir.path().join("story.json"))
+            .await
+            .expect("Failed to load");
+
+        assert_eq!(session, None);
+    }
+
+    #[tokio::test]
+    async fn sessions_are_saved_and_loaded() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join("story.json");
+        let mut session = Session::new(Direction::from("You are reading aloud".to_string()));
+        session.chapters.push(Turn {
+            prompt: Prompt::from("A story about a fox".to_string()),
+            message: Message::from("Once upon a time".to_string()),
+        });
+
+        session.save(&path).await.expect("Failed to save");
+        let loaded = Session::load(&path).await.expect("Failed to load");
+
+        assert_eq!(loaded, Some(session));
+        assert!(
+            !tempdir.path().join("story.json.tmp").exists(),
+            "Expected the temporary file to be moved into place"
+        );
+    }
+
+    #[test]
+    fn sessions_are_readable_json() {
+        let session: Session = serde_json::from_str(
+            r#"{
+                "direction": "You are reading aloud",
+                "chapters": [{"prompt": "A story about a fox", "message": "Once upon a time"}]
+            }"#,
+        )
+        .expect("Failed to parse");
+
+        assert_eq!(session.chapters.len(), 1);
+    }
+}
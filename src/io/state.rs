@@ -0,0 +1,244 @@
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Write},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use miette::{IntoDiagnostic, Result};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::instrument;
+
+use super::write_atomically;
+use crate::remote::feed::Item;
+
+/// Articles that have already been narrated, grouped by the URL of the feed they came from
+#[derive(Deserialize, Serialize, Debug, Default, PartialEq, Eq)]
+pub struct State {
+    #[serde(default)]
+    pub feeds: BTreeMap<String, BTreeMap<String, Narrated>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Narrated {
+    pub title: Option<String>,
+    pub narrated_at: DateTime<Utc>,
+}
+
+impl State {
+    /// Load the state, treating a file that does not exist yet as empty
+    #[instrument]
+    pub async fn load<P: AsRef<Path> + Debug + Sync + Send>(path: P) -> Result<Self> {
+        match tokio::fs::read(path.as_ref()).await {
+            Ok(contents) => serde_json::from_slice(&contents).into_diagnostic(),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error).into_diagnostic(),
+        }
+    }
+
+    /// Save the state, replacing the file in one step so an interrupted run can't corrupt it
+    #[instrument(skip(self))]
+    pub async fn save<P: AsRef<Path> + Debug + Sync + Send>(&self, path: P) -> Result<()> {
+        let contents = serde_json::to_vec_pretty(self).into_diagnostic()?;
+        write_atomically(path.as_ref(), &contents).await
+    }
+
+    pub fn is_narrated(&self, feed: &Url, item: &Item) -> bool {
+        self.feeds
+            .get(feed.as_str())
+            .is_some_and(|narrated| narrated.contains_key(&key(item)))
+    }
+
+    pub fn mark_narrated(&mut self, feed: &Url, item: &Item) {
+        self.feeds.entry(feed.to_string()).or_default().insert(
+            key(item),
+            Narrated {
+                title: item.title.clone(),
+                narrated_at: Utc::now(),
+            },
+        );
+    }
+
+    /// Forget what has been narrated, either for one feed or for all of them
+    pub fn reset(&mut self, feed: Option<&Url>) {
+        match feed {
+            Some(feed) => {
+                self.feeds.remove(feed.as_str());
+            }
+            None => self.feeds.clear(),
+        }
+    }
+}
+
+/// The entry's GUID, or a hash of its title and content for feeds that don't give one
+fn key(item: &Item) -> String {
+    if let Some(id) = &item.id {
+        return id.clone();
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(item.title.as_deref().unwrap_or_default());
+    hasher.update([0]);
+    hasher.update(&item.content);
+    hasher
+        .finalize()
+        .iter()
+        .fold(String::from("sha256:"), |mut key, byte| {
+            write!(key, "{byte:02x}").expect("Infallible");
+            key
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn item(id: Option<&str>, content: &str) -> Item {
+        Item {
+            id: id.map(ToString::to_string),
+            title: Some("The Fox".to_string()),
+            time: None,
+            content: content.to_string(),
+            link: None,
+        }
+    }
+
+    fn feed(url: &str) -> Url {
+        Url::parse(url).expect("Invalid URL")
+    }
+
+    #[test]
+    fn items_are_not_narrated_in_a_new_state() {
+        let state = State::default();
+
+        assert!(
+            !state.is_narrated(&feed("https://example.com/feed"), &item(Some("1"), "Story")),
+            "Expected an empty state to have narrated nothing"
+        );
+    }
+
+    #[test]
+    fn marked_items_are_narrated() {
+        let mut state = State::default();
+        state.mark_narrated(&feed("https://example.com/feed"), &item(Some("1"), "Story"));
+
+        assert!(
+            state.is_narrated(&feed("https://example.com/feed"), &item(Some("1"), "Story")),
+            "Expected the item to be narrated"
+        );
+    }
+
+    #[test]
+    fn items_are_matched_by_id_even_if_their_content_changes() {
+        let mut state = State::default();
+        state.mark_narrated(&feed("https://example.com/feed"), &item(Some("1"), "Story"));
+
+        assert!(
+            state.is_narrated(
+                &feed("https://example.com/feed"),
+                &item(Some("1"), "Edited")
+            ),
+            "Expected the item to be matched by id"
+        );
+    }
+
+    #[test]
+    fn items_without_an_id_are_matched_by_content() {
+        let mut state = State::default();
+        state.mark_narrated(&feed("https://example.com/feed"), &item(None, "Story"));
+
+        assert!(
+            state.is_narrated(&feed("https://example.com/feed"), &item(None, "Story")),
+            "Expected identical content to match"
+        );
+        assert!(
+            !state.is_narrated(
+                &feed("https://example.com/feed"),
+                &item(None, "Another story")
+            ),
+            "Expected different content not to match"
+        );
+    }
+
+    #[test]
+    fn items_are_tracked_per_feed() {
+        let mut state = State::default();
+        state.mark_narrated(&feed("https://example.com/feed"), &item(Some("1"), "Story"));
+
+        assert!(
+            !state.is_narrated(&feed("https://example.org/feed"), &item(Some("1"), "Story")),
+            "Expected the item to be unknown to another feed"
+        );
+    }
+
+    #[test]
+    fn reset_forgets_a_single_feed() {
+        let mut state = State::default();
+        state.mark_narrated(&feed("https://example.com/feed"), &item(Some("1"), "Story"));
+        state.mark_narrated(&feed("https://example.org/feed"), &item(Some("1"), "Story"));
+
+        state.reset(Some(&feed("https://example.com/feed")));
+
+        assert!(
+            !state.is_narrated(&feed("https://example.com/feed"), &item(Some("1"), "Story")),
+            "Expected the reset feed to be forgotten"
+        );
+        assert!(
+            state.is_narrated(&feed("https://example.org/feed"), &item(Some("1"), "Story")),
+            "Expected other feeds to be kept"
+        );
+    }
+
+    #[test]
+    fn reset_without_a_feed_forgets_everything() {
+        let mut state = State::default();
+        state.mark_narrated(&feed("https://example.com/feed"), &item(Some("1"), "Story"));
+
+        state.reset(None);
+
+        assert_eq!(state, State::default());
+    }
+
+    #[tokio::test]
+    async fn load_treats_a_missing_file_as_empty() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+
+        let state = State::load(tempdir.path().join("state.json"))
+            .await
+            .expect("Failed to load state");
+
+        assert_eq!(state, State::default());
+    }
+
+    #[tokio::test]
+    async fn save_and_load_round_trip() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join("state.json");
+        let mut state = State::default();
+        state.mark_narrated(&feed("https://example.com/feed"), &item(Some("1"), "Story"));
+
+        state.save(&path).await.expect("Failed to save state");
+        let loaded = State::load(&path).await.expect("Failed to load state");
+
+        assert_eq!(loaded, state);
+    }
+
+    #[tokio::test]
+    async fn load_rejects_a_corrupt_file() {
+        let tempdir = tempdir().expect("Failed to create tempdir");
+        let path = tempdir.path().join("state.json");
+        tokio::fs::write(&path, "not json")
+            .await
+            .expect("Failed to create file");
+
+        assert!(
+            State::load(&path).await.is_err(),
+            "Expected a corrupt file to fail to load"
+        );
+    }
+}
@@ -0,0 +1,584 @@
+use std::{
+    collections::VecDeque,
+    fmt::{Debug, Display, Formatter},
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError},
+    time::Duration,
+};
+
+use futures::{Stream, StreamExt};
+use miette::{miette, IntoDiagnostic, Result};
+use tracing::instrument;
+
+use super::audio::Format;
+
+/// How many pieces of audio may wait behind the one playing, so the next is always ready
+const QUEUED_AHEAD: usize = 1;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How much audio is decoded before a piece starts playing, to ride out a slow network
+const BUFFERED_AHEAD: Duration = Duration::from_millis(500);
+/// How many samples are decoded at a time, even so frames of stereo samples stay together
+const DECODED_AT_ONCE: usize = 1024;
+
+/// Audio that is still downloading, which can be played before all of it has arrived
+#[derive(Debug, Clone)]
+pub struct Streaming {
+    shared: Arc<Shared>,
+    format: Format,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    buffer: Mutex<Buffer>,
+    arrived: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Buffer {
+    bytes: Vec<u8>,
+    finished: bool,
+    error: Option<String>,
+}
+
+/// Reads a [`Streaming`] from the start, blocking until the bytes it wants have arrived
+#[derive(Debug)]
+pub struct Reader {
+    shared: Arc<Shared>,
+    position: u64,
+}
+
+/// Uncompressed mono samples, played as they arrive
+#[derive(Debug)]
+struct Samples {
+    reader: BufReader<Reader>,
+    sample_rate: u32,
+    ulaw: bool,
+}
+
+/// Samples decoded on a thread of their own, as the output device asks for samples from a
+/// callback that mustn't wait for the network
+#[derive(Debug)]
+struct Decoded {
+    decoding: Arc<Mutex<Decoding>>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+#[derive(Debug, Default)]
+struct Decoding {
+    samples: VecDeque<i16>,
+    finished: bool,
+}
+
+/// Plays audio on the default output device, one piece straight after another
+///
+/// The output device has to be used from the thread that opened it, so a speaker can't be sent
+/// to another thread, and nor can futures that use one.
+pub struct Speaker {
+    _stream: rodio::OutputStream,
+    sink: rodio::Sink,
+}
+
+impl Streaming {
+    /// Download `stream`, audio in `format`, in the background
+    pub fn new<S, B, E>(stream: S, format: Format) -> Self
+    where
+        S: Stream<Item = Result<B, E>> + Send + 'static,
+        B: AsRef<[u8]>,
+        E: Display,
+    {
+        let shared = Arc::new(Shared::default());
+
+        let downloading = Arc::clone(&shared);
+        tokio::spawn(async move {
+            let mut stream = Box::pin(stream);
+            while let Some(bytes) = stream.next().await {
+                match bytes {
+                    Ok(bytes) => downloading.lock().bytes.extend_from_slice(bytes.as_ref()),
+                    Err(error) => {
+                        tracing::warn!("Audio stopped downloading: {}", error);
+                        downloading.lock().error = Some(error.to_string());
+                        break;
+                    }
+                }
+                downloading.arrived.notify_all();
+            }
+
+            downloading.lock().finished = true;
+            downloading.arrived.notify_all();
+        });
+
+        Self { shared, format }
+    }
+
+    pub fn reader(&self) -> Reader {
+        Reader {
+            shared: Arc::clone(&self.shared),
+            position: 0,
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Buffer> {
+        self.buffer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wait until `ready` is true of the buffer, or everything has arrived
+    fn wait_for(&self, ready: impl Fn(&Buffer) -> bool) -> MutexGuard<'_, Buffer> {
+        let mut buffer = self.lock();
+        while !ready(&buffer) && !buffer.finished {
+            buffer = self
+                .arrived
+                .wait(buffer)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        buffer
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let position = usize::try_from(self.position).map_err(io::Error::other)?;
+        let buffer = self.shared.wait_for(|buffer| buffer.bytes.len() > position);
+
+        if let Some(available) = buffer
+            .bytes
+            .get(position..)
+            .filter(|bytes| !bytes.is_empty())
+        {
+            let length = available.len().min(buf.len());
+            buf[..length].copy_from_slice(&available[..length]);
+            self.position += length as u64;
+            return Ok(length);
+        }
+
+        buffer
+            .error
+            .as_ref()
+            .map_or(Ok(0), |error| Err(io::Error::other(error.clone())))
+    }
+}
+
+impl Seek for Reader {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let position = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            // The end is only known once everything has arrived
+            SeekFrom::End(offset) => {
+                let length = self.shared.wait_for(|_| false).bytes.len() as u64;
+                length.checked_add_signed(offset)
+            }
+        };
+
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seeked before the start")
+        })?;
+        Ok(self.position)
+    }
+}
+
+impl Iterator for Samples {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.ulaw {
+            let mut byte = [0; 1];
+            self.reader.read_exact(&mut byte).ok()?;
+            Some(ulaw_to_linear(byte[0]))
+        } else {
+            let mut bytes = [0; 2];
+            self.reader.read_exact(&mut bytes).ok()?;
+            Some(i16::from_le_bytes(bytes))
+        }
+    }
+}
+
+impl rodio::Source for Samples {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+/// Expand a G.711 μ-law sample to 16 bits
+fn ulaw_to_linear(byte: u8) -> i16 {
+    let byte = !byte;
+    let exponent = (byte >> 4) & 0x07;
+    let mantissa = i16::from(byte & 0x0F);
+    let magnitude = (((mantissa << 3) + 0x84) << exponent) - 0x84;
+
+    if byte & 0x80 == 0 {
+        magnitude
+    } else {
+        -magnitude
+    }
+}
+
+impl Decoded {
+    /// Decode `source` on a thread of its own, which waits for the audio to arrive
+    fn spawn<S: rodio::Source<Item = i16> + Send + 'static>(mut source: S) -> Self {
+        let channels = source.channels();
+        let sample_rate = source.sample_rate();
+        let decoding = Arc::new(Mutex::new(Decoding::default()));
+
+        let decoded = Arc::clone(&decoding);
+        std::thread::spawn(move || loop {
+            let samples: Vec<i16> = source.by_ref().take(DECODED_AT_ONCE).collect();
+            let finished = samples.len() < DECODED_AT_ONCE;
+            {
+                let mut decoding = decoded.lock().unwrap_or_else(PoisonError::into_inner);
+                decoding.samples.extend(samples);
+                decoding.finished = finished;
+            }
+            if finished {
+                break;
+            }
+        });
+
+        Self {
+            decoding,
+            channels,
+            sample_rate,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Decoding> {
+        self.decoding.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether enough has been decoded to start playing, or all of it has
+    fn is_ready(&self) -> bool {
+        let ahead = BUFFERED_AHEAD.as_millis() * u128::from(self.sample_rate) / 1000
+            * u128::from(self.channels);
+        let (finished, decoded) = {
+            let decoding = self.lock();
+            (decoding.finished, decoding.samples.len())
+        };
+        finished || decoded as u128 >= ahead
+    }
+}
+
+impl Iterator for Decoded {
+    type Item = i16;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut decoding = self.lock();
+        match decoding.samples.pop_front() {
+            Some(sample) => Some(sample),
+            None if decoding.finished => None,
+            // Audio that hasn't arrived yet is played as silence, rather than stalling the device
+            None => Some(0),
+        }
+    }
+}
+
+impl rodio::Source for Decoded {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+impl Speaker {
+    #[instrument]
+    pub fn try_new() -> Result<Self> {
+        let (stream, handle) = rodio::OutputStream::try_default().into_diagnostic()?;
+        let sink = rodio::Sink::try_new(&handle).into_diagnostic()?;
+
+        Ok(Self {
+            _stream: stream,
+            sink,
+        })
+    }
+
+    /// Play `audio` once everything queued before it has played, then wait until it is next in
+    /// line, so the caller fetches the following piece while this one plays
+    #[allow(
+        clippy::future_not_send,
+        reason = "The output device can't be sent between threads"
+    )]
+    #[instrument]
+    pub async fn queue(&self, audio: Streaming) -> Result<()> {
+        let reader = audio.reader();
+        let decoded = match audio.format {
+            Format::Mp3 { .. } => {
+                // Decoding starts by reading the first frames, which may not have arrived yet
+                let source = tokio::task::spawn_blocking(move || rodio::Decoder::new_mp3(reader))
+                    .await
+                    .into_diagnostic()?
+                    .into_diagnostic()?;
+                Decoded::spawn(source)
+            }
+            // WAV arrives without its header, which is only added when it is saved
+            Format::Pcm { sample_rate } | Format::Wav { sample_rate } => Decoded::spawn(Samples {
+                reader: BufReader::new(reader),
+                sample_rate,
+                ulaw: false,
+            }),
+            Format::Ulaw { sample_rate } => Decoded::spawn(Samples {
+                reader: BufReader::new(reader),
+                sample_rate,
+                ulaw: true,
+            }),
+            format @ Format::Opus { .. } => {
+                return Err(miette!("{format} audio can't be played, only saved"));
+            }
+        };
+
+        while !decoded.is_ready() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        self.sink.append(decoded);
+
+        while self.sink.len() > QUEUED_AHEAD {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+        Ok(())
+    }
+
+    /// Wait for everything queued to finish playing
+    #[allow(
+        clippy::future_not_send,
+        reason = "The output device can't be sent between threads"
+    )]
+    #[instrument]
+    pub async fn finish(self) {
+        while !self.sink.empty() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    }
+}
+
+impl Debug for Speaker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Speaker")
+            .field("queued", &self.sink.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Seek, SeekFrom},
+        time::Duration,
+    };
+
+    use futures::{stream, StreamExt};
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+    use crate::io::mp3::SMALLEST_SYNTACTICALLY_VALID_MP3;
+
+    fn read_to_end(mut reader: Reader) -> Vec<u8> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes).expect("Failed to read");
+        bytes
+    }
+
+    #[tokio::test]
+    async fn reads_everything_that_arrives() {
+        let audio = Streaming::new(
+            stream::iter(vec![
+                Ok::<_, String>(vec![1, 2]),
+                Ok(vec![3]),
+                Ok(vec![4, 5]),
+            ]),
+            Format::default(),
+        );
+
+        let reader = audio.reader();
+        let bytes = tokio::task::spawn_blocking(move || read_to_end(reader))
+            .await
+            .expect("Failed to join");
+
+        assert_eq!(bytes, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn reads_wait_for_bytes_that_have_not_arrived() {
+        let slow =
+            stream::iter(vec![Ok::<_, String>(vec![1, 2]), Ok(vec![3])]).then(|bytes| async move {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                bytes
+            });
+        let audio = Streaming::new(slow, Format::default());
+
+        let reader = audio.reader();
+        let bytes = tokio::task::spawn_blocking(move || read_to_end(reader))
+            .await
+            .expect("Failed to join");
+
+        assert_eq!(bytes, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn seeking_back_rereads_what_arrived() {
+        let audio = Streaming::new(
+            stream::iter(vec![Ok::<_, String>(vec![1, 2, 3])]),
+            Format::default(),
+        );
+
+        let mut reader = audio.reader();
+        let bytes = tokio::task::spawn_blocking(move || {
+            let mut first = [0; 2];
+            reader.read_exact(&mut first).expect("Failed to read");
+            reader.seek(SeekFrom::Start(1)).expect("Failed to seek");
+            let mut rest = vec![];
+            reader.read_to_end(&mut rest).expect("Failed to read");
+            (first, rest)
+        })
+        .await
+        .expect("Failed to join");
+
+        assert_eq!(bytes, ([1, 2], vec![2, 3]));
+    }
+
+    #[tokio::test]
+    async fn seeking_from_the_end_waits_for_everything() {
+        let audio = Streaming::new(
+            stream::iter(vec![Ok::<_, String>(vec![1, 2]), Ok(vec![3, 4])]),
+            Format::default(),
+        );
+
+        let mut reader = audio.reader();
+        let position = tokio::task::spawn_blocking(move || reader.seek(SeekFrom::End(-1)))
+            .await
+            .expect("Failed to join")
+            .expect("Failed to seek");
+
+        assert_eq!(position, 3);
+    }
+
+    #[tokio::test]
+    async fn download_errors_are_read_errors() {
+        let audio = Streaming::new(
+            stream::iter(vec![Ok(vec![1, 2]), Err("Connection reset".to_string())]),
+            Format::default(),
+        );
+
+        let mut reader = audio.reader();
+        let result = tokio::task::spawn_blocking(move || {
+            let mut bytes = vec![];
+            reader.read_to_end(&mut bytes)
+        })
+        .await
+        .expect("Failed to join");
+
+        assert!(result.is_err(), "Expected the download error to be read");
+    }
+
+    #[tokio::test]
+    async fn streamed_mp3s_can_be_decoded() {
+        let audio = Streaming::new(
+            stream::iter(vec![Ok::<_, String>(
+                SMALLEST_SYNTACTICALLY_VALID_MP3.repeat(4),
+            )]),
+            Format::default(),
+        );
+
+        let reader = audio.reader();
+        let decoded = tokio::task::spawn_blocking(move || rodio::Decoder::new_mp3(reader))
+            .await
+            .expect("Failed to join");
+
+        assert!(decoded.is_ok(), "Expected the MP3 to decode");
+    }
+
+    #[tokio::test]
+    async fn streamed_samples_are_read_as_they_arrive() {
+        let audio = Streaming::new(
+            stream::iter(vec![
+                Ok::<_, String>(vec![0xE8, 0x03]),
+                Ok(vec![0x18, 0xFC]),
+            ]),
+            Format::Pcm { sample_rate: 24000 },
+        );
+
+        let reader = audio.reader();
+        let samples = tokio::task::spawn_blocking(move || {
+            Samples {
+                reader: BufReader::new(reader),
+                sample_rate: 24000,
+                ulaw: false,
+            }
+            .collect::<Vec<_>>()
+        })
+        .await
+        .expect("Failed to join");
+
+        assert_eq!(samples, vec![1000, -1000]);
+    }
+
+    #[test]
+    fn decoded_audio_plays_silence_rather_than_waiting() {
+        // The second buffer never arrives, as the sender is kept until the end of the test
+        let (sender, buffers) = std::sync::mpsc::channel();
+        sender
+            .send(SamplesBuffer::<i16>::new(1, 24000, vec![]))
+            .expect("Failed to send");
+
+        let mut decoded = Decoded::spawn(rodio::source::from_iter(buffers));
+
+        assert_eq!(decoded.next(), Some(0));
+        assert!(!decoded.is_ready(), "Expected nothing to have been decoded");
+        drop(sender);
+    }
+
+    #[tokio::test]
+    async fn decoded_audio_ends_with_the_source() {
+        let decoded = Decoded::spawn(SamplesBuffer::new(1, 24000, vec![1000_i16, -1000]));
+        while !decoded.is_ready() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        assert_eq!(decoded.collect::<Vec<_>>(), vec![1000, -1000]);
+    }
+
+    #[test]
+    fn ulaw_is_expanded_to_sixteen_bits() {
+        assert_eq!(ulaw_to_linear(0xFF), 0);
+        assert_eq!(ulaw_to_linear(0x80), 32124);
+        assert_eq!(ulaw_to_linear(0x00), -32124);
+    }
+
+    #[ignore = "This test requires an audio device, which most CI environments do not have"]
+    #[tokio::test]
+    async fn speaker_plays_streamed_audio() {
+        let speaker = Speaker::try_new().expect("Failed to open the speaker");
+        for _ in 0..2 {
+            let audio = Streaming::new(
+                stream::iter(vec![Ok::<_, String>(
+                    SMALLEST_SYNTACTICALLY_VALID_MP3.to_vec(),
+                )]),
+                Format::default(),
+            );
+            speaker.queue(audio).await.expect("Failed to queue audio");
+        }
+        speaker.finish().await;
+    }
+}
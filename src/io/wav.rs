@@ -0,0 +1,136 @@
+const CHANNELS: u16 = 1;
+const BYTES_PER_SAMPLE: u16 = 2;
+const PCM: u16 = 1;
+
+/// A WAV file around raw 16 bit little endian mono samples, with `tags` in a `LIST` `INFO` chunk
+/// such as `(*b"INAM", "The Fox")`
+pub fn file(samples: &[u8], sample_rate: u32, tags: &[([u8; 4], String)]) -> Vec<u8> {
+    let mut format = vec![];
+    format.extend_from_slice(&PCM.to_le_bytes());
+    format.extend_from_slice(&CHANNELS.to_le_bytes());
+    format.extend_from_slice(&sample_rate.to_le_bytes());
+    format.extend_from_slice(&(sample_rate * u32::from(CHANNELS * BYTES_PER_SAMPLE)).to_le_bytes());
+    format.extend_from_slice(&(CHANNELS * BYTES_PER_SAMPLE).to_le_bytes());
+    format.extend_from_slice(&(BYTES_PER_SAMPLE * 8).to_le_bytes());
+
+    let mut body = b"WAVE".to_vec();
+    chunk(&mut body, *b"fmt ", &format);
+    if !tags.is_empty() {
+        let mut info = b"INFO".to_vec();
+        for (id, value) in tags {
+            // Strings in INFO chunks end with a zero byte
+            let mut value = value.as_bytes().to_vec();
+            value.push(0);
+            chunk(&mut info, *id, &value);
+        }
+        chunk(&mut body, *b"LIST", &info);
+    }
+    chunk(&mut body, *b"data", samples);
+
+    let mut file = vec![];
+    chunk(&mut file, *b"RIFF", &body);
+    file
+}
+
+/// The sample rate and samples of a WAV file of 16 bit mono PCM
+///
+/// Programs that write WAV as they go can't know its length up front, so a data chunk that
+/// claims to run past the end of the file is taken to be the rest of it.
+pub fn samples(file: &[u8]) -> Option<(u32, &[u8])> {
+    if file.get(..4)? != b"RIFF" || file.get(8..12)? != b"WAVE" {
+        return None;
+    }
+
+    let mut sample_rate = None;
+    let mut position = 12;
+    while let Some(header) = file.get(position..position + 8) {
+        let length = usize::try_from(u32::from_le_bytes(header[4..8].try_into().ok()?)).ok()?;
+        let start = position + 8;
+        let data = file
+            .get(start..start.saturating_add(length))
+            .unwrap_or_else(|| &file[start..]);
+
+        match &header[..4] {
+            b"fmt " => {
+                let channels = u16::from_le_bytes(data.get(2..4)?.try_into().ok()?);
+                let bits = u16::from_le_bytes(data.get(14..16)?.try_into().ok()?);
+                if channels != CHANNELS || bits != BYTES_PER_SAMPLE * 8 {
+                    return None;
+                }
+                sample_rate = Some(u32::from_le_bytes(data.get(4..8)?.try_into().ok()?));
+            }
+            b"data" => return Some((sample_rate?, data)),
+            _ => {}
+        }
+        position = start.saturating_add(length + length % 2);
+    }
+    None
+}
+
+/// Append a chunk, padded to an even length as RIFF requires
+fn chunk(into: &mut Vec<u8>, id: [u8; 4], data: &[u8]) {
+    into.extend_from_slice(&id);
+    into.extend_from_slice(&u32::try_from(data.len()).unwrap_or(u32::MAX).to_le_bytes());
+    into.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        into.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::{file, samples};
+
+    #[test]
+    fn files_can_be_decoded() {
+        let samples: Vec<u8> = [0_i16, 1000, -1000, 0]
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+
+        let wav = file(&samples, 24000, &[(*b"INAM", "The Fox".to_string())]);
+        let decoder = rodio::Decoder::new_wav(Cursor::new(wav)).expect("Failed to decode");
+
+        assert_eq!(rodio::Source::sample_rate(&decoder), 24000);
+        assert_eq!(decoder.collect::<Vec<i16>>(), vec![0, 1000, -1000, 0]);
+    }
+
+    #[test]
+    fn odd_chunks_are_padded() {
+        let wav = file(&[], 8000, &[(*b"INAM", "Foxy".to_string())]);
+
+        assert_eq!(wav.len() % 2, 0, "Expected the file to be an even length");
+        assert_eq!(
+            u32::from_le_bytes(wav[4..8].try_into().expect("Too short")) as usize,
+            wav.len() - 8,
+            "Expected the RIFF length to cover the rest of the file"
+        );
+    }
+
+    #[test]
+    fn samples_are_read_back() {
+        let wav = file(&[1, 2, 3, 4], 22050, &[(*b"INAM", "Foxy".to_string())]);
+
+        assert_eq!(samples(&wav), Some((22050, &[1_u8, 2, 3, 4][..])));
+    }
+
+    #[test]
+    fn unfinished_lengths_are_the_rest_of_the_file() {
+        let mut wav = file(&[1, 2, 3, 4], 22050, &[]);
+        // The data chunk's length is just before its four bytes of samples
+        let length = wav.len() - 8;
+        wav[length..length + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        assert_eq!(samples(&wav), Some((22050, &[1_u8, 2, 3, 4][..])));
+    }
+
+    #[test]
+    fn stereo_is_refused() {
+        let mut wav = file(&[1, 2, 3, 4], 22050, &[]);
+        wav[22] = 2;
+
+        assert_eq!(samples(&wav), None);
+    }
+}
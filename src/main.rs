@@ -27,20 +27,46 @@ mod command;
 mod io;
 mod logging;
 mod remote;
-
-use std::{ops::Sub, path::PathBuf, str::FromStr, time};
+mod text;
+
+use std::{
+    ffi::OsString,
+    num::{NonZeroU32, NonZeroUsize},
+    ops::Sub,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    str::FromStr,
+    time,
+};
 
 use chrono::Utc;
-use clap::{Parser, Subcommand};
-use command::read_aloud;
-use html2text::render::text_renderer::TrivialDecorator;
-use miette::{miette, IntoDiagnostic, Result};
-use quick_xml::escape as xml_escape;
-use remote::{chatgpt, elevenlabs};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use command::{cache, feed_to_audio, read_aloud, state, voices};
+use miette::{miette, IntoDiagnostic, Report, Result};
+use remote::{
+    chatgpt::{self, AnyGenerator},
+    deepl,
+    elevenlabs,
+    feed,
+    google_translate,
+    morss,
+    openai,
+    readability,
+    retry,
+    translate,
+    tts::{self, AnyTts},
+};
 use reqwest::Url;
-use serde::{Deserialize, Serialize};
 
-use crate::{io::audio::Audio, remote::elevenlabs::Repository};
+use crate::{
+    io::{
+        audio::Format,
+        cache::Cache,
+        local_tts::Local,
+        output::{self, Template},
+    },
+    remote::{elevenlabs::Repository, feed::FeedSource, translate::AnyTranslator},
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -55,18 +81,37 @@ struct Cli {
     /// and also warn for hello
     #[arg(short, long, env, default_value = "info")]
     rust_log: String,
+
+    /// How many times to send a request to a remote service before giving up
+    ///
+    /// Requests are sent again when they time out, can't connect, are rate limited, or get a
+    /// server error
+    #[arg(long, env, global = true, default_value_t = retry::Policy::default().max_attempts)]
+    max_attempts: NonZeroU32,
+
+    /// How long to wait for a remote service to answer each request, such as "30s" or "2m"
+    #[arg(long, env, global = true, default_value = "2m", value_parser = parse_duration)]
+    request_timeout: time::Duration,
+
+    /// The longest to wait when a remote service asks to be tried again later, such as "5m"
+    #[arg(long, env, global = true, default_value = "5m", value_parser = parse_duration)]
+    max_retry_wait: time::Duration,
 }
 
 #[derive(Subcommand, Debug)]
+#[allow(
+    clippy::large_enum_variant,
+    reason = "Parsed once at startup, so the size doesn't matter"
+)]
 enum Commands {
     /// Read a prompt from ChatGPT aloud
     ReadAloud {
-        /// Key for ChatGPT
+        /// Key for ChatGPT, or for the server at --llm-base-url if it needs one
         #[arg(short, long, env)]
-        chatgpt_key: chatgpt::Key,
-        /// Key for ElevenLabs
+        chatgpt_key: Option<chatgpt::Key>,
+        /// Key for ElevenLabs, needed unless reading with a local engine
         #[arg(short, long, env)]
-        elevenlabs_key: elevenlabs::Key,
+        elevenlabs_key: Option<elevenlabs::Key>,
         /// Prompt to give to ChatGPT
         #[arg(short = 'p', long, env)]
         chatgpt_prompt: chatgpt::Prompt,
@@ -74,34 +119,81 @@ enum Commands {
         #[arg(short = 'd', long, env, default_value = "You are reading aloud")]
         chatgpt_direction: chatgpt::Direction,
 
-        /// ID of the voice to use
+        #[command(flatten)]
+        generation: GenerationArgs,
+
+        /// ID or name of the voice to use, which also narrates when characters have voices
         #[arg(short = 'v', long, env, default_value = "MF3mGyEYCl7XYWbV9V6O")]
         elevenlabs_voice: elevenlabs::Voice,
+        /// Give a character their own ElevenLabs voice, such as "Fox=Rachel", which asks for the
+        /// story as dialogue. Can be given more than once
+        #[arg(long, env = "CHARACTER_VOICES", value_delimiter = ',', value_parser = parse_character_voice)]
+        character_voice: Vec<(String, elevenlabs::Voice)>,
+
+        #[command(flatten)]
+        speech: SpeechArgs,
 
         /// Save to a file rather than reading aloud
         #[arg(short, long, env)]
         output: Option<PathBuf>,
+
+        /// Save to a file named from a template rather than reading aloud, using the prompt as
+        /// the article title, for example "{published:%Y-%m-%d}-{article_title_slug}.mp3"
+        #[arg(short = 't', long, env, conflicts_with = "output")]
+        output_template: Option<Template>,
+
+        /// How many pieces of a long story to synthesize at once
+        #[arg(long, env, default_value = "1")]
+        concurrency: NonZeroUsize,
     },
     /// Read a prompt from ChatGPT aloud
     FeedToAudio {
         /// Url of the RSS feed
         #[arg(short, long, env)]
         url: Url,
-        /// Key for ElevenLabs
+
+        /// Where to fetch the feed from
+        #[arg(short = 'f', long, env, value_enum, default_value_t = FeedBackend::Direct)]
+        feed_source: FeedBackend,
+
+        /// Base URL of the morss instance to use with the morss feed source
+        #[arg(long, env, default_value = morss::DEFAULT_HOST)]
+        morss_host: Url,
+        /// Key for ElevenLabs, needed unless reading with a local engine
         #[arg(short, long, env)]
-        elevenlabs_key: elevenlabs::Key,
+        elevenlabs_key: Option<elevenlabs::Key>,
 
-        /// ID of the voice to use
+        /// ID or name of the voice to use
         #[arg(short = 'v', long, env, default_value = "MF3mGyEYCl7XYWbV9V6O")]
         elevenlabs_voice: elevenlabs::Voice,
 
+        #[command(flatten)]
+        speech: SpeechArgs,
+
+        /// Service to translate articles with, picked from the key given when not set
+        ///
+        /// Articles are read as they are without a translator, and are never sent to one when
+        /// they are already in the target language
+        #[arg(long, env, value_enum)]
+        translator: Option<TranslatorBackend>,
+
         /// Key for Google Translate
         #[arg(short, long, env)]
-        google_translate_key: String,
-
-        /// Target Language
-        #[arg(short, long, env, default_value = "en")]
-        google_translate_target_lang: String,
+        google_translate_key: Option<google_translate::Key>,
+
+        /// Key for DeepL
+        #[arg(long, env)]
+        deepl_key: Option<deepl::Key>,
+
+        /// Language to translate articles into
+        #[arg(
+            short = 'l',
+            long,
+            env,
+            alias = "google-translate-target-lang",
+            default_value = "en"
+        )]
+        target_language: translate::Language,
 
         /// Articles published after this date
         #[arg(short = 'a', long, env, value_parser = parse_date)]
@@ -111,60 +203,523 @@ enum Commands {
         #[arg(short = 'w', long, env, value_parser = parse_duration)]
         articles_published_within: Option<time::Duration>,
 
+        /// Follow each article's link and read the full page rather than the feed's summary
+        #[arg(long, env)]
+        full_article: bool,
+
+        /// Remember which articles have been narrated in this file, and skip them next time
+        #[arg(short, long, env)]
+        state_file: Option<PathBuf>,
+
+        /// Save to files in this directory rather than reading aloud
+        #[arg(short, long, env)]
+        output: Option<PathBuf>,
+
+        /// How to name the files saved to the output directory
+        ///
+        /// Can use `{feed_title}`, `{article_title_slug}`, `{published}` or
+        /// `{published:<strftime format>}`, `{article_index}` and `{chunk}`. Using `{chunk}` saves
+        /// every chunk of an article to its own file. Names that are already taken get a counter
+        /// added
+        #[arg(short = 't', long, env, default_value = output::DEFAULT_TEMPLATE)]
+        output_template: Template,
+
+        /// Write a podcast feed to the output directory, with episodes served from this URL
+        #[arg(short, long, env, requires = "output")]
+        podcast_base_url: Option<Url>,
+
+        /// Artwork for the podcast feed, which Apple Podcasts needs to list it, a square JPEG or
+        /// PNG between 1400 and 3000 pixels across
+        #[arg(long, env, requires = "podcast_base_url")]
+        podcast_image: Option<Url>,
+
+        /// How many articles to prepare, and requests to send to each service, at once
+        ///
+        /// Files, podcast episodes and playback stay in the order the feed lists the articles
+        #[arg(long, env, default_value = "1")]
+        concurrency: NonZeroUsize,
+    },
+    /// List the voices that can be given to --elevenlabs-voice
+    Voices {
+        /// Key for ElevenLabs
+        #[arg(short, long, env)]
+        elevenlabs_key: elevenlabs::Key,
+
+        /// How to print the voices
+        #[arg(short, long, env = "VOICES_FORMAT", value_enum, default_value_t = voices::Format::Table)]
+        format: voices::Format,
+    },
+    /// Inspect or reset which articles feed-to-audio has already narrated
+    State {
+        /// File the narrated articles are remembered in
+        #[arg(short, long, env)]
+        state_file: PathBuf,
+
+        #[command(subcommand)]
+        action: StateAction,
+    },
+    /// Tell a story a chapter at a time, across several runs
+    Story {
+        #[command(subcommand)]
+        action: StoryAction,
+    },
+    /// Inspect, prune or clear the audio cached from ElevenLabs
+    Cache {
+        #[command(flatten)]
+        cache: CacheArgs,
+
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+/// What writes the story, and how
+#[derive(Args, Debug)]
+struct GenerationArgs {
+    /// Generate with an OpenAI compatible API at this URL, such as
+    /// `http://localhost:11434/v1` for Ollama, rather than ChatGPT
+    #[arg(long, env)]
+    llm_base_url: Option<Url>,
+
+    /// Model to generate with, such as "gpt-4o" or "llama3", rather than "gpt-3.5-turbo"
+    #[arg(long, env)]
+    llm_model: Option<chatgpt::Model>,
+
+    /// From 0, more focused, to 2, more random, rather than the model's default
+    #[arg(long, env, value_parser = parse_temperature)]
+    temperature: Option<f32>,
+
+    /// From 0 to 1, only pick from the most likely words that add up to this much, rather than
+    /// the model's default
+    #[arg(long, env, value_parser = parse_fraction)]
+    top_p: Option<f32>,
+
+    /// The most tokens to generate, about three quarters of a word each, which cuts the story
+    /// off when it is reached
+    #[arg(long, env)]
+    max_output_tokens: Option<NonZeroU32>,
+
+    /// Generate the same story for the same prompt and seed, as far as the model can
+    ///
+    /// ChatGPT can't be sent a seed, so this generates with the OpenAI API, or the API at
+    /// --llm-base-url, instead
+    #[arg(long, env)]
+    seed: Option<i64>,
+}
+
+impl GenerationArgs {
+    /// What to generate with, ChatGPT unless another API, a model or a seed was asked for, and
+    /// how
+    fn generator(
+        self,
+        key: Option<chatgpt::Key>,
+        retry: retry::Policy,
+    ) -> Result<(AnyGenerator, chatgpt::GenerationOptions)> {
+        let generator =
+            if self.llm_base_url.is_some() || self.llm_model.is_some() || self.seed.is_some() {
+                AnyGenerator::OpenAi(openai::OpenAi::try_new(
+                    key,
+                    match self.llm_base_url {
+                        Some(url) => url,
+                        None => Url::parse(openai::DEFAULT_URL).into_diagnostic()?,
+                    },
+                    retry,
+                )?)
+            } else {
+                let key =
+                    key.ok_or_else(|| miette!("--chatgpt-key is needed to generate with ChatGPT"))?;
+                AnyGenerator::ChatGPT(chatgpt::ChatGPT::try_new(key, retry)?)
+            };
+
+        Ok((
+            generator,
+            chatgpt::GenerationOptions {
+                model: self.llm_model,
+                temperature: self.temperature,
+                top_p: self.top_p,
+                max_output_tokens: self.max_output_tokens.map(NonZeroU32::get),
+                seed: self.seed,
+            },
+        ))
+    }
+}
+
+/// How the voice reads
+#[derive(Args, Debug)]
+#[allow(
+    clippy::struct_field_names,
+    reason = "The names are the options, where the prefix says which service they are for"
+)]
+struct SpeechArgs {
+    /// Model to read with, such as `eleven_multilingual_v2`
+    ///
+    /// Defaults to the English only model, unless feed-to-audio's target language isn't English
+    #[arg(long, env)]
+    elevenlabs_model: Option<elevenlabs::Model>,
+
+    /// From 0, more expressive, to 1, more consistent, rather than the voice's own setting
+    #[arg(long, env, value_parser = parse_fraction)]
+    elevenlabs_stability: Option<f32>,
+
+    /// From 0 to 1, how closely to stick to the original voice, rather than the voice's own
+    /// setting
+    #[arg(long, env, value_parser = parse_fraction)]
+    elevenlabs_similarity_boost: Option<f32>,
+
+    /// From 0 to 1, how much to exaggerate the style of the original voice, rather than the
+    /// voice's own setting
+    #[arg(long, env, value_parser = parse_fraction)]
+    elevenlabs_style: Option<f32>,
+
+    /// Whether to sound more like the original speaker, at the cost of being a little slower,
+    /// rather than the voice's own setting
+    #[arg(long, env)]
+    elevenlabs_speaker_boost: Option<bool>,
+
+    /// How to encode the audio, such as `mp3_44100_128`, `wav_24000`, `opus_48000_64` or
+    /// `ulaw_8000`, or just `mp3`, `pcm`, `wav`, `opus` or `ulaw`
+    ///
+    /// Worked out from the extension of the output when not given, and MP3 otherwise. Opus can
+    /// only be saved, not read aloud
+    #[arg(long, env)]
+    format: Option<Format>,
+
+    /// What reads the text aloud
+    #[arg(long, env, value_enum, default_value_t = TtsBackend::Elevenlabs)]
+    tts_backend: TtsBackend,
+
+    /// Voice for espeak-ng to read with, such as "en" or "en-us"
+    #[arg(long, env, default_value = "en")]
+    espeak_voice: String,
+
+    /// Model for Piper to read with, with its configuration beside it as "<model>.json"
+    #[arg(long, env, required_if_eq("tts_backend", "piper"))]
+    piper_model: Option<PathBuf>,
+
+    /// Program to run for a local engine, rather than espeak-ng or piper from the PATH
+    #[arg(long, env)]
+    tts_program: Option<OsString>,
+
+    /// Ask ElevenLabs for every message, rather than reusing audio it read before
+    #[arg(long, env)]
+    no_cache: bool,
+
+    #[command(flatten)]
+    cache: CacheArgs,
+}
+
+/// Where audio ElevenLabs read is kept, and for how long
+#[derive(Args, Debug)]
+#[allow(
+    clippy::struct_field_names,
+    reason = "The names are the options, where the prefix says what they are for"
+)]
+struct CacheArgs {
+    /// Directory to cache audio in, rather than story-time in the user's cache directory
+    #[arg(long, env)]
+    cache_dir: Option<PathBuf>,
+
+    /// Remove cached audio that hasn't been used for this long, such as "7days", rather than
+    /// after 30 days
+    #[arg(long, env, value_parser = parse_duration)]
+    cache_max_age: Option<time::Duration>,
+
+    /// Remove the least recently used audio once the cache is bigger than this, such as
+    /// "500MB", rather than 1GB
+    #[arg(long, env, value_parser = parse_size)]
+    cache_max_size: Option<u64>,
+}
+
+impl CacheArgs {
+    fn cache(self) -> Option<Cache> {
+        let directory = self.cache_dir.or_else(Cache::default_directory)?;
+        let mut cache = Cache::new(directory);
+        if let Some(max_age) = self.cache_max_age {
+            cache = cache.with_max_age(max_age);
+        }
+        if let Some(max_size) = self.cache_max_size {
+            cache = cache.with_max_size(max_size);
+        }
+        Some(cache)
+    }
+}
+
+impl SpeechArgs {
+    /// What to read with, using ElevenLabs' `model` when none was given, and the format the
+    /// extension of `output` suggests when no format was given
+    async fn tts(
+        self,
+        key: Option<elevenlabs::Key>,
+        voice: elevenlabs::Voice,
+        model: elevenlabs::Model,
+        output: Option<&Path>,
+        retry: retry::Policy,
+    ) -> Result<AnyTts> {
+        let format = self.format.or_else(|| output.and_then(Format::from_path));
+
+        let tts = match self.tts_backend {
+            TtsBackend::Elevenlabs => {
+                let format = format.unwrap_or_default();
+                if output.is_none() && !format.is_playable() {
+                    return Err(miette!(
+                        "{format} can only be saved, give an --output to save it to"
+                    ));
+                }
+                let key = key
+                    .ok_or_else(|| miette!("--elevenlabs-key is needed to read with ElevenLabs"))?;
+                let cache = if self.no_cache {
+                    None
+                } else {
+                    let cache = self.cache.cache();
+                    if cache.is_none() {
+                        tracing::warn!("Couldn't find a cache directory, give a --cache-dir");
+                    }
+                    cache
+                };
+                let client = elevenlabs::Reqwest::try_new(
+                    key,
+                    Url::parse(elevenlabs::DEFAULT_URL).into_diagnostic()?,
+                    retry,
+                )?
+                .with_cache(cache);
+                let voice = client.resolve_voice(voice).await?;
+                AnyTts::Elevenlabs(Box::new(tts::Elevenlabs::new(
+                    client,
+                    voice,
+                    elevenlabs::Settings {
+                        model: self.elevenlabs_model.unwrap_or(model),
+                        voice: elevenlabs::VoiceSettings {
+                            stability: self.elevenlabs_stability,
+                            similarity_boost: self.elevenlabs_similarity_boost,
+                            style: self.elevenlabs_style,
+                            use_speaker_boost: self.elevenlabs_speaker_boost,
+                        },
+                        format,
+                    },
+                )))
+            }
+            TtsBackend::Espeak => AnyTts::Local(
+                Local::espeak(self.espeak_voice)
+                    .with_program(self.tts_program)
+                    .with_format(format)?,
+            ),
+            TtsBackend::Piper => {
+                let model = self
+                    .piper_model
+                    .ok_or_else(|| miette!("--piper-model is needed to read with Piper"))?;
+                AnyTts::Local(
+                    Local::piper(model)
+                        .await?
+                        .with_program(self.tts_program)
+                        .with_format(format)?,
+                )
+            }
+        };
+        Ok(tts)
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum StateAction {
+    /// List the articles that have been narrated
+    Show {
+        /// Only show articles from this feed
+        #[arg(short, long)]
+        url: Option<Url>,
+    },
+    /// Forget narrated articles so they are narrated again
+    Reset {
+        /// Only forget articles from this feed
+        #[arg(short, long)]
+        url: Option<Url>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum StoryAction {
+    /// Ask ChatGPT for the next chapter of the story and read it aloud, remembering it so the
+    /// chapter after follows on from it
+    Continue {
+        /// File the story so far is remembered in, which is started when it doesn't exist
+        #[arg(short, long, env)]
+        session_file: PathBuf,
+        /// Key for ChatGPT, or for the server at --llm-base-url if it needs one
+        #[arg(short, long, env)]
+        chatgpt_key: Option<chatgpt::Key>,
+        /// Key for ElevenLabs, needed unless reading with a local engine
+        #[arg(short, long, env)]
+        elevenlabs_key: Option<elevenlabs::Key>,
+        /// Prompt for this chapter, needed to start a story, and otherwise asking for the next
+        /// chapter
+        #[arg(short = 'p', long, env)]
+        chatgpt_prompt: Option<chatgpt::Prompt>,
+        /// A style to read in, kept for the whole story once it has started
+        #[arg(short = 'd', long, env, default_value = "You are reading aloud")]
+        chatgpt_direction: chatgpt::Direction,
+
+        #[command(flatten)]
+        generation: GenerationArgs,
+
+        /// ID or name of the voice to use, which also narrates when characters have voices
+        #[arg(short = 'v', long, env, default_value = "MF3mGyEYCl7XYWbV9V6O")]
+        elevenlabs_voice: elevenlabs::Voice,
+        /// Give a character their own ElevenLabs voice, such as "Fox=Rachel", which asks for the
+        /// story as dialogue. Can be given more than once
+        #[arg(long, env = "CHARACTER_VOICES", value_delimiter = ',', value_parser = parse_character_voice)]
+        character_voice: Vec<(String, elevenlabs::Voice)>,
+
+        #[command(flatten)]
+        speech: SpeechArgs,
+
         /// Save to a file rather than reading aloud
         #[arg(short, long, env)]
         output: Option<PathBuf>,
+
+        /// How many pieces of a long chapter to synthesize at once
+        #[arg(long, env, default_value = "1")]
+        concurrency: NonZeroUsize,
     },
 }
 
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// List the cached audio, most recently used first
+    Show,
+    /// Remove audio that is too old, or that doesn't fit in the cache
+    Prune,
+    /// Remove all the cached audio
+    Clear,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TranslatorBackend {
+    /// Google Cloud Translation
+    GoogleTranslate,
+    /// DeepL, using the free API for keys ending in ":fx"
+    Deepl,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum TtsBackend {
+    /// ElevenLabs, which needs a key
+    Elevenlabs,
+    /// espeak-ng, run on this machine
+    Espeak,
+    /// Piper, run on this machine with a downloaded model
+    Piper,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum FeedBackend {
+    /// Download and parse the feed locally
+    Direct,
+    /// Download the feed through a morss instance
+    Morss,
+}
+
 fn parse_duration(args: &str) -> Result<time::Duration, humantime::DurationError> {
     humantime::Duration::from_str(args).map(humantime::Duration::into)
 }
 
-fn parse_date(args: &str) -> Result<time::SystemTime, humantime::TimestampError> {
-    humantime::parse_rfc3339_weak(args)
+fn parse_fraction(args: &str) -> Result<f32, String> {
+    let fraction: f32 = args.parse().map_err(|error| format!("{error}"))?;
+    if (0.0..=1.0).contains(&fraction) {
+        Ok(fraction)
+    } else {
+        Err("must be between 0 and 1".to_string())
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
-struct Feed {
-    title: Option<String>,
-    items: Vec<Item>,
+/// A number of bytes, optionally in KB, MB or GB, which are each 1024 of the last
+fn parse_size(args: &str) -> Result<u64, String> {
+    let args = args.trim();
+    let (number, unit) = args.split_at(
+        args.find(|character: char| !character.is_ascii_digit())
+            .unwrap_or(args.len()),
+    );
+    let number: u64 = number.parse().map_err(|error| format!("{error}"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1024,
+        "M" | "MB" => 1024 * 1024,
+        "G" | "GB" => 1024 * 1024 * 1024,
+        unit => return Err(format!("unknown unit {unit}, use B, KB, MB or GB")),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| "too big".to_string())
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
-struct Item {
-    title: Option<String>,
-    time: Option<chrono::DateTime<Utc>>,
-    content: String,
+/// A character's name and the voice that reads them, as "Name=Voice"
+fn parse_character_voice(args: &str) -> Result<(String, elevenlabs::Voice), String> {
+    let (name, voice) = args
+        .split_once('=')
+        .ok_or_else(|| "must be a name and a voice, such as Fox=Rachel".to_string())?;
+    let (name, voice) = (name.trim(), voice.trim());
+    if name.is_empty() || voice.is_empty() {
+        return Err("must be a name and a voice, such as Fox=Rachel".to_string());
+    }
+    Ok((name.to_string(), voice.to_string().into()))
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
-struct GoogleTranslation {
-    #[serde(rename = "translatedText")]
-    translated_text: String,
+fn parse_temperature(args: &str) -> Result<f32, String> {
+    let temperature: f32 = args.parse().map_err(|error| format!("{error}"))?;
+    if (0.0..=2.0).contains(&temperature) {
+        Ok(temperature)
+    } else {
+        Err("must be between 0 and 2".to_string())
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
-struct GoogleTranslateResponse {
-    data: GoogleTranslateTranslations,
+fn parse_date(args: &str) -> Result<time::SystemTime, humantime::TimestampError> {
+    humantime::parse_rfc3339_weak(args)
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
-struct GoogleTranslateTranslations {
-    translations: Vec<GoogleTranslation>,
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Error: {error:?}");
+            exit_code(&error)
+        }
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, PartialEq)]
-struct GoogleTranslateRequest {
-    q: Vec<String>,
-    target: String,
+/// Failures talking to a remote service exit with a code for what went wrong, so scripts can
+/// tell a used up quota from a bad key or a network problem
+fn exit_code(error: &Report) -> ExitCode {
+    error
+        .downcast_ref::<elevenlabs::Error>()
+        .map(elevenlabs::Error::kind)
+        .or_else(|| {
+            error
+                .downcast_ref::<chatgpt::Error>()
+                .map(chatgpt::Error::kind)
+        })
+        .or_else(|| {
+            error
+                .downcast_ref::<google_translate::Error>()
+                .map(google_translate::Error::kind)
+        })
+        .or_else(|| error.downcast_ref::<deepl::Error>().map(deepl::Error::kind))
+        .or_else(|| error.downcast_ref::<feed::Error>().map(feed::Error::kind))
+        .map_or(ExitCode::FAILURE, ExitCode::from)
 }
 
-#[tokio::main]
 #[allow(clippy::too_many_lines)]
-async fn main() -> Result<()> {
-    let args = Cli::parse();
+#[allow(
+    clippy::future_not_send,
+    reason = "Playback holds the output device, which can't be sent between threads"
+)]
+async fn run(args: Cli) -> Result<()> {
     logging::setup(&args.rust_log)?;
+    let retry = retry::Policy {
+        max_attempts: args.max_attempts,
+        timeout: args.request_timeout,
+        max_retry_wait: args.max_retry_wait,
+        ..retry::Policy::default()
+    };
 
     match args.command {
         Commands::ReadAloud {
@@ -172,163 +727,239 @@ async fn main() -> Result<()> {
             elevenlabs_key,
             chatgpt_prompt,
             chatgpt_direction,
+            generation,
             elevenlabs_voice,
+            character_voice,
+            speech,
             output,
+            output_template,
+            concurrency,
         } => {
-            let chatgpt_client = chatgpt::ChatGPT::try_new(chatgpt_key)?;
-            let elevenlabs_client = elevenlabs::Reqwest::try_new(elevenlabs_key)?;
-
-            read_aloud::Command::new(chatgpt_client, elevenlabs_client)
-                .run(chatgpt_direction, chatgpt_prompt, elevenlabs_voice, output)
+            let (chatgpt_client, generation_options) = generation.generator(chatgpt_key, retry)?;
+            let output = match output_template {
+                Some(template) => {
+                    let title = chatgpt_prompt.to_string();
+                    let file = template.render(&output::Context {
+                        feed_title: None,
+                        article_title: Some(&title),
+                        published: Utc::now(),
+                        article_index: 1,
+                        chunk: 1,
+                    });
+                    Some(output::available_path(Path::new(""), &file).await?)
+                }
+                None => output,
+            };
+
+            let tts = speech
+                .tts(
+                    elevenlabs_key,
+                    elevenlabs_voice,
+                    elevenlabs::Model::default(),
+                    output.as_deref(),
+                    retry,
+                )
+                .await?;
+            let mut characters = vec![];
+            for (name, voice) in character_voice {
+                characters.push((name, tts.with_voice(voice).await?));
+            }
+            read_aloud::Command::new(chatgpt_client, tts, concurrency)
+                .with_generation_options(generation_options)
+                .with_characters(characters)
+                .run(chatgpt_direction, chatgpt_prompt, output)
                 .await?;
         }
         Commands::FeedToAudio {
             url,
+            feed_source,
+            morss_host,
             elevenlabs_key,
             elevenlabs_voice,
+            speech,
+            translator,
             google_translate_key,
-            google_translate_target_lang,
+            deepl_key,
+            target_language,
             articles_published_after,
             articles_published_within,
+            full_article,
+            state_file,
             output,
+            output_template,
+            podcast_base_url,
+            podcast_image,
+            concurrency,
         } => {
-            let elevenlabs_client = elevenlabs::Reqwest::try_new(elevenlabs_key)?;
-            let client = reqwest::Client::new();
-            let original_host = url.host().ok_or_else(|| miette!("No host"))?;
-
-            let mut morss_url = url.clone();
-            morss_url.set_host(Some("morss.it")).into_diagnostic()?;
-            {
-                let mut path_segments = morss_url.path_segments_mut().expect("Infallible");
-                path_segments.clear();
-                path_segments.extend(&[":format=json:cors", &original_host.to_string()]);
-                let original_path_segments: Vec<&str> = url
-                    .path_segments()
-                    .map(Iterator::collect)
-                    .unwrap_or_default();
-                path_segments.extend(&original_path_segments);
+            if podcast_base_url.is_some() && output_template.is_per_chunk() {
+                return Err(miette!(
+                    "Podcast episodes are one file per article, so the output template can't \
+                     include the chunk"
+                ));
             }
-            morss_url.set_query(url.query());
-
-            let feed = client
-                .get(morss_url.clone())
-                .send()
-                .await
-                .into_diagnostic()?
-                .text()
-                .await
-                .into_diagnostic()?;
-
-            let feed_contents: Feed = serde_json::from_str(feed.as_str()).into_diagnostic()?;
-
-            // Create a DeepL instance for our account.
-            let deepl = reqwest::Client::builder().build().into_diagnostic()?;
-
-            for (article_counter, entry) in feed_contents
-                .items
-                .into_iter()
-                .filter(|entry| {
-                    match (
-                        entry.time,
-                        articles_published_after.map(chrono::DateTime::<Utc>::from),
-                        articles_published_within
-                            .map(|x| time::SystemTime::now().sub(x))
-                            .map(chrono::DateTime::<Utc>::from),
-                    ) {
-                        (Some(_), None, None) | (None, _, _) => true,
-                        (Some(publish_time), None, Some(cutoff_time))
-                        | (Some(publish_time), Some(cutoff_time), None) => {
-                            publish_time > cutoff_time
-                        }
-                        (Some(publish_time), Some(left), Some(right)) => {
-                            publish_time > left.max(right)
-                        }
-                    }
-                })
-                .enumerate()
-            {
-                let mut buf = String::new();
-                if let Some(ref title) = entry.title {
-                    buf.push_str(title);
-                    buf.push_str("\n\n");
+            let template = PathBuf::from(output_template.to_string());
+            let tts = speech
+                .tts(
+                    elevenlabs_key,
+                    elevenlabs_voice,
+                    elevenlabs::Model::for_language(&target_language),
+                    output.as_ref().map(|_| template.as_path()),
+                    retry,
+                )
+                .await?;
+            let mut feed_contents = match feed_source {
+                FeedBackend::Direct => feed::Direct::try_new(retry)?.fetch(&url).await?,
+                FeedBackend::Morss => {
+                    morss::Morss::try_new(morss_host, retry)?
+                        .fetch(&url)
+                        .await?
                 }
-
-                let content = entry.content.as_bytes();
-                let decorator = TrivialDecorator::new();
-                let clean_text =
-                    html2text::from_read_with_decorator(content, usize::MAX, decorator);
-                buf.push_str(&clean_text);
-                buf.push_str("\n\n");
-
-                let mut url =
-                    Url::parse("https://translation.googleapis.com/language/translate/v2")
-                        .into_diagnostic()?;
-                url.query_pairs_mut()
-                    .append_pair("key", &google_translate_key);
-
-                let response: GoogleTranslateResponse = deepl
-                    .post(url)
-                    .json(&GoogleTranslateRequest {
-                        q: vec![buf],
-                        target: google_translate_target_lang.clone(),
-                    })
-                    .send()
-                    .await
-                    .into_diagnostic()?
-                    .error_for_status()
-                    .into_diagnostic()?
-                    .json()
-                    .await
-                    .into_diagnostic()?;
-
-                let translated_text = response
-                    .data
-                    .translations
-                    .into_iter()
-                    .map(|x| x.translated_text)
-                    .collect::<Vec<_>>()
-                    .join("\n\n");
-
-                for (paragraph_counter, text) in translated_text
-                    .split_inclusive('.')
-                    .fold(vec![String::new()], |acc, text| {
-                        let mut new_vec = acc;
-                        let mut last_item = new_vec
-                            .pop()
-                            .expect("We initialise the list with at least one item");
-
-                        if last_item.len() + text.len() > 5000 {
-                            new_vec.push(last_item);
-                            new_vec.push(text.to_string());
-                        } else {
-                            last_item.push_str(text);
-                            new_vec.push(last_item);
-                        }
-
-                        new_vec
-                    })
-                    .into_iter()
-                    .enumerate()
-                {
-                    let audio = elevenlabs_client
-                        .text_to_speech(
-                            elevenlabs_voice.clone(),
-                            xml_escape::unescape(&text)
-                                .map(|x| x.to_string())
-                                .unwrap_or(text)
-                                .to_string(),
-                        )
-                        .await?;
-
-                    if let Some(mut path) = output.clone() {
-                        path.push(format!("{}-{}.mp3", paragraph_counter, article_counter));
-                        audio.save(path).await?;
-                    } else {
-                        audio.play()?;
-                    }
+            };
+            let article_client = full_article
+                .then(|| readability::Reqwest::try_new(retry))
+                .transpose()?;
+
+            let translator = match (translator, google_translate_key, deepl_key) {
+                (Some(TranslatorBackend::GoogleTranslate) | None, Some(key), _) => Some(
+                    AnyTranslator::GoogleTranslate(google_translate::GoogleTranslate::try_new(
+                        key,
+                        Url::parse(google_translate::DEFAULT_URL).into_diagnostic()?,
+                        retry,
+                    )?),
+                ),
+                (Some(TranslatorBackend::Deepl) | None, _, Some(key)) => {
+                    let url = Url::parse(key.default_url()).into_diagnostic()?;
+                    Some(AnyTranslator::Deepl(deepl::Deepl::try_new(
+                        key, url, retry,
+                    )?))
+                }
+                (Some(TranslatorBackend::GoogleTranslate), None, _) => {
+                    return Err(miette!(
+                        "--google-translate-key is needed to translate with Google"
+                    ));
                 }
+                (Some(TranslatorBackend::Deepl), _, None) => {
+                    return Err(miette!("--deepl-key is needed to translate with DeepL"));
+                }
+                (None, None, None) => None,
+            };
+
+            feed_contents.items.retain(|entry| {
+                match (
+                    entry.time,
+                    articles_published_after.map(chrono::DateTime::<Utc>::from),
+                    articles_published_within
+                        .map(|x| time::SystemTime::now().sub(x))
+                        .map(chrono::DateTime::<Utc>::from),
+                ) {
+                    (Some(_), None, None) | (None, _, _) => true,
+                    (Some(publish_time), None, Some(cutoff_time))
+                    | (Some(publish_time), Some(cutoff_time), None) => publish_time > cutoff_time,
+                    (Some(publish_time), Some(left), Some(right)) => publish_time > left.max(right),
+                }
+            });
+
+            feed_to_audio::Command::new(tts, target_language, concurrency)
+                .with_translator(translator)
+                .with_article_client(article_client)
+                .with_output(output, output_template, podcast_base_url)
+                .with_podcast_image(podcast_image)
+                .with_state_file(state_file)
+                .run(&url, feed_contents)
+                .await?;
+        }
+        Commands::Voices {
+            elevenlabs_key,
+            format,
+        } => {
+            let elevenlabs_client = elevenlabs::Reqwest::try_new(
+                elevenlabs_key,
+                Url::parse(elevenlabs::DEFAULT_URL).into_diagnostic()?,
+                retry,
+            )?;
+            voices::Command::new(elevenlabs_client).run(format).await?;
+        }
+        Commands::State { state_file, action } => {
+            let command = state::Command::new(state_file);
+            match action {
+                StateAction::Show { url } => command.show(url).await?,
+                StateAction::Reset { url } => command.reset(url).await?,
+            }
+        }
+        Commands::Story {
+            action:
+                StoryAction::Continue {
+                    session_file,
+                    chatgpt_key,
+                    elevenlabs_key,
+                    chatgpt_prompt,
+                    chatgpt_direction,
+                    generation,
+                    elevenlabs_voice,
+                    character_voice,
+                    speech,
+                    output,
+                    concurrency,
+                },
+        } => {
+            let (chatgpt_client, generation_options) = generation.generator(chatgpt_key, retry)?;
+            let tts = speech
+                .tts(
+                    elevenlabs_key,
+                    elevenlabs_voice,
+                    elevenlabs::Model::default(),
+                    output.as_deref(),
+                    retry,
+                )
+                .await?;
+            let mut characters = vec![];
+            for (name, voice) in character_voice {
+                characters.push((name, tts.with_voice(voice).await?));
+            }
+            read_aloud::Command::new(chatgpt_client, tts, concurrency)
+                .with_generation_options(generation_options)
+                .with_characters(characters)
+                .continue_story(session_file, chatgpt_direction, chatgpt_prompt, output)
+                .await?;
+        }
+        Commands::Cache { cache, action } => {
+            let cache = cache
+                .cache()
+                .ok_or_else(|| miette!("Couldn't find a cache directory, give a --cache-dir"))?;
+            let command = cache::Command::new(cache);
+            match action {
+                CacheAction::Show => command.show().await?,
+                CacheAction::Prune => command.prune().await?,
+                CacheAction::Clear => command.clear().await?,
             }
         }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeds_are_sent_to_the_openai_api() {
+        let args = GenerationArgs {
+            llm_base_url: None,
+            llm_model: None,
+            temperature: None,
+            top_p: None,
+            max_output_tokens: None,
+            seed: Some(7),
+        };
+
+        let (generator, options) = args
+            .generator(None, retry::Policy::default())
+            .expect("Failed to build the generator");
+
+        assert!(
+            matches!(generator, AnyGenerator::OpenAi(_)),
+            "Expected ChatGPT, which can't be sent a seed, not to be used"
+        );
+        assert_eq!(options.seed, Some(7));
+    }
+}
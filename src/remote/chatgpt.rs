@@ -1,14 +1,169 @@
 use std::fmt::{Debug, Display, Formatter};
 
 use async_trait::async_trait;
-use chatgpt::client;
-use miette::{IntoDiagnostic, Result};
+use chatgpt::{
+    client,
+    config::ModelConfiguration,
+    err::Error as ClientError,
+    types::{ChatMessage, Role},
+};
+use miette::{Diagnostic, IntoDiagnostic, Result};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use tracing::instrument;
 
+use super::{
+    error::Kind,
+    openai::OpenAi,
+    retry::{Failure, Policy},
+};
+
+pub const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+/// The type and code of an error for a used up quota
+const INSUFFICIENT_QUOTA: &str = "insufficient_quota";
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Error {
+    #[error("ChatGPT didn't accept the key: {message}")]
+    #[diagnostic(
+        code(story_time::chatgpt::unauthorized),
+        help("check CHATGPT_KEY is a current API key")
+    )]
+    Unauthorized { message: String },
+
+    #[error("ChatGPT quota is used up: {message}")]
+    #[diagnostic(
+        code(story_time::chatgpt::quota_exceeded),
+        help("check the plan and billing details of the account")
+    )]
+    QuotaExceeded { message: String },
+
+    #[error("ChatGPT is still rate limiting requests: {message}")]
+    #[diagnostic(
+        code(story_time::chatgpt::rate_limited),
+        help("wait a little, or try more --max-attempts")
+    )]
+    RateLimited { message: String },
+
+    #[error("ChatGPT is unavailable: {message}")]
+    #[diagnostic(code(story_time::chatgpt::unavailable), help("try again later"))]
+    Unavailable { message: String },
+
+    #[error("ChatGPT rejected the request: {message}")]
+    #[diagnostic(code(story_time::chatgpt::rejected))]
+    Rejected { message: String },
+
+    #[error("Couldn't get a story from ChatGPT")]
+    #[diagnostic(
+        code(story_time::chatgpt::network),
+        help("check the network connection, or try a longer --request-timeout")
+    )]
+    Network(#[source] reqwest::Error),
+
+    #[error("ChatGPT's answer couldn't be understood: {message}")]
+    #[diagnostic(code(story_time::chatgpt::invalid_response))]
+    InvalidResponse { message: String },
+}
+
+/// The body sent with an error status, which some servers give as just a message
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    error: ErrorDetails,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ErrorDetails {
+    Described {
+        message: String,
+        #[serde(rename = "type")]
+        error_type: Option<String>,
+        code: Option<serde_json::Value>,
+    },
+    Message(String),
+}
+
+impl Error {
+    /// Work out what went wrong from an error status and the body that came with it, as an
+    /// `OpenAI` compatible server sends them
+    pub fn from_response(status: StatusCode, body: &str) -> Self {
+        let (message, quota_exceeded) = match serde_json::from_str::<ErrorBody>(body) {
+            Ok(ErrorBody {
+                error:
+                    ErrorDetails::Described {
+                        message,
+                        error_type,
+                        code,
+                    },
+            }) => {
+                let quota_exceeded = error_type.as_deref() == Some(INSUFFICIENT_QUOTA)
+                    || code.as_ref().and_then(serde_json::Value::as_str)
+                        == Some(INSUFFICIENT_QUOTA);
+                (message, quota_exceeded)
+            }
+            Ok(ErrorBody {
+                error: ErrorDetails::Message(message),
+            }) => (message, false),
+            Err(_) if body.trim().is_empty() => (status.to_string(), false),
+            Err(_) => (body.trim().to_string(), false),
+        };
+
+        if quota_exceeded {
+            return Self::QuotaExceeded { message };
+        }
+
+        match Kind::of_status(status) {
+            Kind::Unauthorized => Self::Unauthorized { message },
+            Kind::RateLimited => Self::RateLimited { message },
+            Kind::Unavailable => Self::Unavailable { message },
+            _ => Self::Rejected { message },
+        }
+    }
+
+    pub const fn kind(&self) -> Kind {
+        match self {
+            Self::Unauthorized { .. } => Kind::Unauthorized,
+            Self::QuotaExceeded { .. } => Kind::QuotaExceeded,
+            Self::RateLimited { .. } => Kind::RateLimited,
+            Self::Unavailable { .. } => Kind::Unavailable,
+            Self::Rejected { .. } => Kind::Rejected,
+            Self::Network(_) => Kind::Network,
+            Self::InvalidResponse { .. } => Kind::InvalidResponse,
+        }
+    }
+}
+
+/// The client only says what went wrong, not the status, so failures are recognised by the type
+/// of error the API reports
+impl From<ClientError> for Error {
+    fn from(error: ClientError) -> Self {
+        match error {
+            ClientError::ClientError(error) if error.is_decode() => Self::InvalidResponse {
+                message: error.to_string(),
+            },
+            ClientError::ClientError(error) => Self::Network(error),
+            ClientError::BackendError {
+                message,
+                error_type,
+            } => match error_type.as_str() {
+                INSUFFICIENT_QUOTA => Self::QuotaExceeded { message },
+                "requests" | "tokens" => Self::RateLimited { message },
+                "server_error" => Self::Unavailable { message },
+                _ if message.contains("API key") => Self::Unauthorized { message },
+                _ => Self::Rejected { message },
+            },
+            error => Self::InvalidResponse {
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ChatGPT {
     client: client::ChatGPT,
+    retry: Policy,
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
@@ -99,6 +254,58 @@ impl From<Message> for String {
     }
 }
 
+/// The name of the model to generate with, such as "gpt-4o" or "llama3"
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Model(String);
+
+impl Default for Model {
+    fn default() -> Self {
+        Self(DEFAULT_MODEL.to_string())
+    }
+}
+
+impl Display for Model {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<String> for Model {
+    fn from(v: String) -> Self {
+        Self(v)
+    }
+}
+
+impl From<Model> for String {
+    fn from(v: Model) -> Self {
+        v.0
+    }
+}
+
+/// How to generate, each left to the model's own default when not given
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GenerationOptions {
+    /// Which model to generate with, rather than [`DEFAULT_MODEL`], which only [`OpenAi`] sends
+    pub model: Option<Model>,
+    /// From 0, more focused, to 2, more random
+    pub temperature: Option<f32>,
+    /// From 0 to 1, only pick from the most likely tokens that add up to this much
+    pub top_p: Option<f32>,
+    /// The most tokens to generate, which cuts the text off when it is reached
+    pub max_output_tokens: Option<u32>,
+    /// Generate the same text for the same prompt and seed, as far as the model can
+    pub seed: Option<i64>,
+}
+
+/// A prompt and the message written for it, one exchange of a conversation
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct Turn {
+    pub prompt: Prompt,
+    pub message: Message,
+}
+
+/// Something that writes text for a prompt, following a direction on how to write it
 #[async_trait]
 pub trait Repository {
     async fn generate_text<
@@ -108,42 +315,161 @@ pub trait Repository {
         &self,
         direction: D,
         prompt: P,
+        options: &GenerationOptions,
+    ) -> Result<Message> {
+        self.continue_conversation(&direction.into(), &[], prompt, options)
+            .await
+    }
+
+    /// Write the message for `prompt` following on from `history`, the earlier turns of a
+    /// conversation that started with `direction`
+    async fn continue_conversation<P: Into<Prompt> + Debug + Sync + Send>(
+        &self,
+        direction: &Direction,
+        history: &[Turn],
+        prompt: P,
+        options: &GenerationOptions,
     ) -> Result<Message>;
 }
 
 #[async_trait]
 impl Repository for ChatGPT {
     #[instrument]
-    async fn generate_text<D, P>(&self, direction: D, prompt: P) -> Result<Message>
-    where
-        D: Into<Direction> + Debug + Sync + Send,
-        P: Into<Prompt> + Debug + Sync + Send,
-    {
+    async fn continue_conversation<P: Into<Prompt> + Debug + Sync + Send>(
+        &self,
+        direction: &Direction,
+        history: &[Turn],
+        prompt: P,
+        options: &GenerationOptions,
+    ) -> Result<Message> {
+        let mut messages = vec![ChatMessage {
+            role: Role::System,
+            content: direction.to_string(),
+        }];
+        for turn in history {
+            messages.push(ChatMessage {
+                role: Role::User,
+                content: turn.prompt.to_string(),
+            });
+            messages.push(ChatMessage {
+                role: Role::Assistant,
+                content: turn.message.to_string(),
+            });
+        }
+        messages.push(ChatMessage {
+            role: Role::User,
+            content: prompt.into().into(),
+        });
+        let mut client = self.client.clone();
+        client.config = configuration(client.config, options);
+
         let response = self
-            .client
-            .new_conversation_directed(String::from(direction.into()))
-            .send_message(String::from(prompt.into()))
-            .await
-            .into_diagnostic()?;
+            .retry
+            .run(|| async { client.send_history(&messages).await.map_err(failure) })
+            .await?;
         let message = response.message().clone().content;
         Ok(message.into())
     }
 }
 
+/// Whichever text generator was picked on the command line
+#[derive(Debug)]
+pub enum AnyGenerator {
+    ChatGPT(ChatGPT),
+    OpenAi(OpenAi),
+}
+
+#[async_trait]
+impl Repository for AnyGenerator {
+    #[instrument]
+    async fn continue_conversation<P: Into<Prompt> + Debug + Sync + Send>(
+        &self,
+        direction: &Direction,
+        history: &[Turn],
+        prompt: P,
+        options: &GenerationOptions,
+    ) -> Result<Message> {
+        match self {
+            Self::ChatGPT(generator) => {
+                generator
+                    .continue_conversation(direction, history, prompt, options)
+                    .await
+            }
+            Self::OpenAi(generator) => {
+                generator
+                    .continue_conversation(direction, history, prompt, options)
+                    .await
+            }
+        }
+    }
+}
+
 impl ChatGPT {
     // Instrument panic is false positive
     #[allow(clippy::panic_in_result_fn)]
     #[instrument]
-    pub fn try_new<T: Into<Key> + Debug>(key: T) -> Result<Self> {
-        let client = client::ChatGPT::new(key.into()).into_diagnostic()?;
+    pub fn try_new<T: Into<Key> + Debug>(key: T, retry: Policy) -> Result<Self> {
+        let client = client::ChatGPT::new_with_config(
+            key.into(),
+            ModelConfiguration {
+                timeout: retry.timeout,
+                ..ModelConfiguration::default()
+            },
+        )
+        .into_diagnostic()?;
+
+        Ok(Self { client, retry })
+    }
+}
 
-        Ok(Self { client })
+/// The client's `config` with `options` in place of its defaults
+///
+/// Asking for a model picks [`OpenAi`] rather than [`ChatGPT`], so this always generates with
+/// [`DEFAULT_MODEL`].
+const fn configuration(
+    mut config: ModelConfiguration,
+    options: &GenerationOptions,
+) -> ModelConfiguration {
+    if let Some(temperature) = options.temperature {
+        config.temperature = temperature;
+    }
+    if let Some(top_p) = options.top_p {
+        config.top_p = top_p;
+    }
+    if options.max_output_tokens.is_some() {
+        config.max_tokens = options.max_output_tokens;
+    }
+    config
+}
+
+fn failure(error: ClientError) -> Failure<Error> {
+    let error = Error::from(error);
+
+    match error.kind() {
+        Kind::RateLimited | Kind::Unavailable | Kind::Network => Failure::Transient {
+            error,
+            retry_after: None,
+        },
+        _ => Failure::Permanent(error),
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Direction, Key, Message, Prompt};
+    use chatgpt::{config::ModelConfiguration, err::Error as ClientError};
+    use reqwest::StatusCode;
+
+    use super::{
+        configuration,
+        failure,
+        Direction,
+        Error,
+        GenerationOptions,
+        Key,
+        Message,
+        Prompt,
+    };
+    use crate::remote::{error::Kind, retry::Failure};
 
     #[test]
     fn direction_is_a_string_in_json() {
@@ -252,4 +578,101 @@ mod tests {
             "\"test\"".to_string()
         );
     }
+
+    #[test]
+    fn rate_limits_and_server_errors_are_transient() {
+        for error_type in ["requests", "tokens", "server_error"] {
+            let error = ClientError::BackendError {
+                message: "Try again later".to_string(),
+                error_type: error_type.to_string(),
+            };
+
+            assert!(
+                matches!(failure(error), Failure::Transient { .. }),
+                "Expected {error_type} to be transient"
+            );
+        }
+    }
+
+    #[test]
+    fn bad_keys_are_permanent() {
+        let error = ClientError::BackendError {
+            message: "Incorrect API key provided: sk-1234".to_string(),
+            error_type: "invalid_request_error".to_string(),
+        };
+
+        assert!(
+            matches!(
+                failure(error),
+                Failure::Permanent(error) if error.kind() == Kind::Unauthorized
+            ),
+            "Expected a bad key to be a permanent authorization failure"
+        );
+    }
+
+    #[test]
+    fn used_up_quotas_are_told_apart() {
+        let error = Error::from(ClientError::BackendError {
+            message: "You exceeded your current quota".to_string(),
+            error_type: "insufficient_quota".to_string(),
+        });
+
+        assert_eq!(error.kind(), Kind::QuotaExceeded);
+        assert_eq!(
+            error.to_string(),
+            "ChatGPT quota is used up: You exceeded your current quota"
+        );
+    }
+
+    #[test]
+    fn error_statuses_are_told_apart_by_their_body() {
+        let error = Error::from_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            r#"{"error": {"message": "You exceeded your current quota", "type": "insufficient_quota", "code": "insufficient_quota"}}"#,
+        );
+        assert_eq!(error.kind(), Kind::QuotaExceeded);
+
+        let error = Error::from_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            r#"{"error": {"message": "Slow down", "type": "requests", "code": null}}"#,
+        );
+        assert_eq!(error.kind(), Kind::RateLimited);
+    }
+
+    #[test]
+    fn plain_error_messages_are_kept() {
+        let error = Error::from_response(
+            StatusCode::NOT_FOUND,
+            r#"{"error": "model 'llama3' not found"}"#,
+        );
+
+        assert_eq!(
+            error.to_string(),
+            "ChatGPT rejected the request: model 'llama3' not found"
+        );
+    }
+
+    #[test]
+    fn options_replace_the_client_defaults() {
+        let config = configuration(
+            ModelConfiguration::default(),
+            &GenerationOptions {
+                model: None,
+                temperature: Some(0.2),
+                top_p: None,
+                max_output_tokens: Some(500),
+                seed: None,
+            },
+        );
+
+        assert!(
+            (config.temperature - 0.2).abs() < f32::EPSILON,
+            "Expected the temperature to be replaced"
+        );
+        assert!(
+            (config.top_p - ModelConfiguration::default().top_p).abs() < f32::EPSILON,
+            "Expected the default top p to be kept"
+        );
+        assert_eq!(config.max_tokens, Some(500));
+    }
 }
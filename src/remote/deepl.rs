@@ -0,0 +1,384 @@
+use std::fmt::{Debug, Display, Formatter};
+
+use async_trait::async_trait;
+use miette::{Diagnostic, IntoDiagnostic, Result};
+use reqwest::{header::HeaderMap, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::instrument;
+
+use super::{
+    error::Kind,
+    retry::Policy,
+    translate::{Language, Translation, Translator},
+};
+
+pub const PRO_URL: &str = "https://api.deepl.com/v2/translate";
+pub const FREE_URL: &str = "https://api-free.deepl.com/v2/translate";
+
+/// Translate with the [DeepL API](https://www.deepl.com/docs-api)
+#[derive(Debug)]
+pub struct Deepl {
+    client: reqwest::Client,
+    url: Url,
+    retry: Policy,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Key(String);
+
+impl Key {
+    /// Free and pro accounts use different hosts, and free keys end in ":fx"
+    pub fn default_url(&self) -> &'static str {
+        if self.0.ends_with(":fx") {
+            FREE_URL
+        } else {
+            PRO_URL
+        }
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<String> for Key {
+    fn from(v: String) -> Self {
+        Self(v)
+    }
+}
+
+impl From<Key> for String {
+    fn from(v: Key) -> Self {
+        v.0
+    }
+}
+
+/// The status for a used up character quota
+const QUOTA_EXCEEDED: u16 = 456;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Error {
+    #[error("DeepL didn't accept the key: {message}")]
+    #[diagnostic(
+        code(story_time::deepl::unauthorized),
+        help("check DEEPL_KEY is a current authentication key")
+    )]
+    Unauthorized { message: String },
+
+    #[error("DeepL quota is used up: {message}")]
+    #[diagnostic(
+        code(story_time::deepl::quota_exceeded),
+        help("wait for the quota to reset, or move to a plan with more characters")
+    )]
+    QuotaExceeded { message: String },
+
+    #[error("DeepL is still rate limiting requests: {message}")]
+    #[diagnostic(
+        code(story_time::deepl::rate_limited),
+        help("try a lower --concurrency, or more --max-attempts")
+    )]
+    RateLimited { message: String },
+
+    #[error("DeepL is unavailable ({status}): {message}")]
+    #[diagnostic(code(story_time::deepl::unavailable), help("try again later"))]
+    Unavailable { status: u16, message: String },
+
+    #[error("DeepL rejected the request ({status}): {message}")]
+    #[diagnostic(
+        code(story_time::deepl::rejected),
+        help("check --target-language is a language DeepL supports")
+    )]
+    Rejected { status: u16, message: String },
+
+    #[error("Couldn't reach DeepL")]
+    #[diagnostic(
+        code(story_time::deepl::network),
+        help("check the network connection, or try a longer --request-timeout")
+    )]
+    Network(#[source] reqwest::Error),
+
+    #[error("DeepL's answer couldn't be understood")]
+    #[diagnostic(code(story_time::deepl::invalid_response))]
+    InvalidResponse(#[source] reqwest::Error),
+}
+
+/// The body sent with an error status
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    message: String,
+}
+
+impl Error {
+    /// Work out what went wrong from an error status and the body that came with it
+    fn from_response(status: StatusCode, body: &str) -> Self {
+        let message = match serde_json::from_str::<ErrorBody>(body) {
+            Ok(ErrorBody { message }) => message,
+            Err(_) if body.trim().is_empty() => status.to_string(),
+            Err(_) => body.trim().to_string(),
+        };
+
+        if status.as_u16() == QUOTA_EXCEEDED {
+            return Self::QuotaExceeded { message };
+        }
+
+        match Kind::of_status(status) {
+            Kind::Unauthorized => Self::Unauthorized { message },
+            Kind::RateLimited => Self::RateLimited { message },
+            Kind::Unavailable => Self::Unavailable {
+                status: status.as_u16(),
+                message,
+            },
+            _ => Self::Rejected {
+                status: status.as_u16(),
+                message,
+            },
+        }
+    }
+
+    pub const fn kind(&self) -> Kind {
+        match self {
+            Self::Unauthorized { .. } => Kind::Unauthorized,
+            Self::QuotaExceeded { .. } => Kind::QuotaExceeded,
+            Self::RateLimited { .. } => Kind::RateLimited,
+            Self::Unavailable { .. } => Kind::Unavailable,
+            Self::Rejected { .. } => Kind::Rejected,
+            Self::Network(_) => Kind::Network,
+            Self::InvalidResponse(_) => Kind::InvalidResponse,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            Self::InvalidResponse(error)
+        } else {
+            Self::Network(error)
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Request {
+    text: Vec<String>,
+    target_lang: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Response {
+    translations: Vec<DeeplTranslation>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct DeeplTranslation {
+    text: String,
+    detected_source_language: Option<String>,
+}
+
+impl Deepl {
+    /// Create a client for the translation endpoint at `url`, usually [`Key::default_url`]
+    #[allow(
+        clippy::panic_in_result_fn,
+        reason = "The instrument macro is a false positive"
+    )]
+    #[instrument]
+    pub fn try_new<T: Into<Key> + Debug>(key: T, url: Url, retry: Policy) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "Authorization",
+            format!("DeepL-Auth-Key {}", key.into())
+                .try_into()
+                .into_diagnostic()?,
+        );
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .into_diagnostic()?;
+
+        Ok(Self { client, url, retry })
+    }
+}
+
+#[async_trait]
+impl Translator for Deepl {
+    #[instrument]
+    async fn translate<
+        T: Into<String> + Debug + Sync + Send,
+        L: Into<Language> + Debug + Sync + Send,
+    >(
+        &self,
+        text: T,
+        target: L,
+    ) -> Result<Translation> {
+        let request = self.client.post(self.url.clone()).json(&Request {
+            text: vec![text.into()],
+            // DeepL's codes are upper case, "EN-GB" rather than "en-GB"
+            target_lang: String::from(target.into()).to_uppercase(),
+        });
+        let response = self.retry.send(request).await.map_err(Error::from)?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.map_err(Error::from)?;
+            return Err(Error::from_response(status, &body).into());
+        }
+        let response: Response = response.json().await.map_err(Error::from)?;
+
+        let detected_source_language = response
+            .translations
+            .iter()
+            .find_map(|translation| translation.detected_source_language.as_deref())
+            .map(|language| Language::from(language.to_lowercase()));
+        let text = response
+            .translations
+            .into_iter()
+            .map(|translation| translation.text)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        Ok(Translation {
+            text,
+            detected_source_language,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{StatusCode, Url};
+    use wiremock::{
+        matchers::{body_json, header, method, path},
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use super::{Deepl, Error, Key, FREE_URL, PRO_URL};
+    use crate::remote::{
+        error::Kind,
+        retry::Policy,
+        translate::{Language, Translation, Translator},
+    };
+
+    fn translator(server: &MockServer) -> Deepl {
+        let url = Url::parse(&format!("{}/v2/translate", server.uri())).expect("Invalid URL");
+        Deepl::try_new(Key::from("secret".to_string()), url, Policy::default())
+            .expect("Failed to create client")
+    }
+
+    #[test]
+    fn free_keys_use_the_free_api() {
+        assert_eq!(Key::from("secret:fx".to_string()).default_url(), FREE_URL);
+        assert_eq!(Key::from("secret".to_string()).default_url(), PRO_URL);
+    }
+
+    #[test]
+    fn key_implements_display() {
+        let key = Key("test".to_string());
+        assert_eq!(key.to_string(), "test");
+    }
+
+    #[test]
+    fn key_implements_into_string() {
+        let key: String = Key("test".to_string()).into();
+        assert_eq!(key, "test".to_string());
+    }
+
+    #[tokio::test]
+    async fn translate_sends_the_text_and_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/translate"))
+            .and(header("Authorization", "DeepL-Auth-Key secret"))
+            .and(body_json(serde_json::json!({
+                "text": ["Der schnelle braune Fuchs."],
+                "target_lang": "EN-GB"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "translations": [
+                    {
+                        "detected_source_language": "DE",
+                        "text": "The quick brown fox."
+                    }
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let translation = translator(&server)
+            .translate(
+                "Der schnelle braune Fuchs.",
+                Language::from("en-GB".to_string()),
+            )
+            .await
+            .expect("Failed to translate");
+
+        assert_eq!(
+            translation,
+            Translation {
+                text: "The quick brown fox.".to_string(),
+                detected_source_language: Some(Language::from("de".to_string())),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn translate_fails_on_an_error_status() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+
+        let result = translator(&server)
+            .translate(
+                "Der schnelle braune Fuchs.",
+                Language::from("en".to_string()),
+            )
+            .await;
+
+        assert!(result.is_err(), "Expected a 403 to be an error");
+    }
+
+    #[tokio::test]
+    async fn bad_keys_are_unauthorized() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(403)
+                    .set_body_json(serde_json::json!({ "message": "Wrong endpoint" })),
+            )
+            .mount(&server)
+            .await;
+
+        let error = translator(&server)
+            .translate(
+                "Der schnelle braune Fuchs.",
+                Language::from("en".to_string()),
+            )
+            .await
+            .expect_err("Expected a 403 to be an error");
+
+        let error = error
+            .downcast_ref::<Error>()
+            .expect("Expected a DeepL error");
+        assert_eq!(error.kind(), Kind::Unauthorized);
+        assert_eq!(
+            error.to_string(),
+            "DeepL didn't accept the key: Wrong endpoint"
+        );
+    }
+
+    #[test]
+    fn used_up_quotas_are_told_apart() {
+        let status = StatusCode::from_u16(456).expect("Invalid status");
+        let error = Error::from_response(status, r#"{"message": "Quota exceeded"}"#);
+
+        assert_eq!(error.kind(), Kind::QuotaExceeded);
+    }
+}
@@ -1,17 +1,177 @@
-use std::fmt::{Debug, Display, Formatter};
+use std::{
+    collections::BTreeMap,
+    fmt::{Debug, Display, Formatter},
+};
 
 use async_trait::async_trait;
 use chatgpt::prelude::Url;
-use miette::{IntoDiagnostic, Result};
-use reqwest::header::HeaderMap;
+use futures::stream;
+use miette::{miette, Diagnostic, IntoDiagnostic, Result};
+use reqwest::{header::HeaderMap, StatusCode};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use tracing::instrument;
 
-use super::super::io::audio::{Audio, VecU8A};
+use super::{
+    super::io::{
+        audio::{Audio, Format, VecU8A},
+        cache::Cache,
+        stream::Streaming,
+    },
+    error::Kind,
+    retry::Policy,
+    translate::Language,
+};
 
-#[derive(Debug)]
+/// The most characters the text to speech endpoint will read in one request
+pub const MAX_CHARACTERS: usize = 5000;
+pub const DEFAULT_URL: &str = "https://api.elevenlabs.io/v1";
+/// Reads English only
+pub const MONOLINGUAL_MODEL: &str = "eleven_monolingual_v1";
+/// Reads most languages, including English
+pub const MULTILINGUAL_MODEL: &str = "eleven_multilingual_v2";
+/// Voice IDs are this many letters and digits, which no voice name is
+const VOICE_ID_LENGTH: usize = 20;
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Error {
+    #[error("ElevenLabs didn't accept the key: {message}")]
+    #[diagnostic(
+        code(story_time::elevenlabs::unauthorized),
+        help("check ELEVENLABS_KEY is a current API key")
+    )]
+    Unauthorized { message: String },
+
+    #[error("ElevenLabs quota is used up: {message}")]
+    #[diagnostic(
+        code(story_time::elevenlabs::quota_exceeded),
+        help("wait for the quota to reset, or move to a plan with more characters")
+    )]
+    QuotaExceeded { message: String },
+
+    #[error("ElevenLabs has no voice {voice}: {message}")]
+    #[diagnostic(
+        code(story_time::elevenlabs::voice_not_found),
+        help("check ELEVENLABS_VOICE is the ID or name of a voice on this account, `story-time voices` lists them")
+    )]
+    VoiceNotFound { voice: Voice, message: String },
+
+    #[error("More than one ElevenLabs voice is called {voice}: {ids}")]
+    #[diagnostic(
+        code(story_time::elevenlabs::ambiguous_voice),
+        help("use the ID of the voice instead")
+    )]
+    AmbiguousVoice { voice: Voice, ids: String },
+
+    #[error("ElevenLabs is still rate limiting requests: {message}")]
+    #[diagnostic(
+        code(story_time::elevenlabs::rate_limited),
+        help("try a lower --concurrency, or more --max-attempts")
+    )]
+    RateLimited { message: String },
+
+    #[error("ElevenLabs is unavailable ({status}): {message}")]
+    #[diagnostic(code(story_time::elevenlabs::unavailable), help("try again later"))]
+    Unavailable { status: u16, message: String },
+
+    #[error("ElevenLabs rejected the request ({status}): {message}")]
+    #[diagnostic(code(story_time::elevenlabs::rejected))]
+    Rejected { status: u16, message: String },
+
+    #[error("Couldn't reach ElevenLabs")]
+    #[diagnostic(
+        code(story_time::elevenlabs::network),
+        help("check the network connection, or try a longer --request-timeout")
+    )]
+    Network(#[source] reqwest::Error),
+
+    #[error("ElevenLabs' answer couldn't be understood")]
+    #[diagnostic(code(story_time::elevenlabs::invalid_response))]
+    InvalidResponse(#[source] reqwest::Error),
+}
+
+/// The body sent with an error status
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    detail: ErrorDetail,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ErrorDetail {
+    Status { status: String, message: String },
+    Invalid(Vec<InvalidField>),
+    Message(String),
+}
+
+#[derive(Deserialize, Debug)]
+struct InvalidField {
+    msg: String,
+}
+
+impl Error {
+    /// Work out what went wrong from an error status and the body that came with it, from a
+    /// request for `voice` if it was for one
+    fn from_response(voice: Option<Voice>, status: StatusCode, body: &str) -> Self {
+        let (reason, message) = match serde_json::from_str::<ErrorBody>(body) {
+            Ok(ErrorBody {
+                detail: ErrorDetail::Status { status, message },
+            }) => (Some(status), message),
+            Ok(ErrorBody {
+                detail: ErrorDetail::Invalid(fields),
+            }) => (
+                None,
+                fields
+                    .into_iter()
+                    .map(|field| field.msg)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            Ok(ErrorBody {
+                detail: ErrorDetail::Message(message),
+            }) => (None, message),
+            Err(_) if body.trim().is_empty() => (None, status.to_string()),
+            Err(_) => (None, body.trim().to_string()),
+        };
+
+        match (reason.as_deref(), Kind::of_status(status), voice) {
+            (Some("quota_exceeded"), _, _) => Self::QuotaExceeded { message },
+            (Some("voice_not_found"), _, Some(voice)) | (_, Kind::NotFound, Some(voice)) => {
+                Self::VoiceNotFound { voice, message }
+            }
+            (_, Kind::Unauthorized, _) => Self::Unauthorized { message },
+            (_, Kind::RateLimited, _) => Self::RateLimited { message },
+            (_, Kind::Unavailable, _) => Self::Unavailable {
+                status: status.as_u16(),
+                message,
+            },
+            _ => Self::Rejected {
+                status: status.as_u16(),
+                message,
+            },
+        }
+    }
+
+    pub const fn kind(&self) -> Kind {
+        match self {
+            Self::Unauthorized { .. } => Kind::Unauthorized,
+            Self::QuotaExceeded { .. } => Kind::QuotaExceeded,
+            Self::VoiceNotFound { .. } => Kind::NotFound,
+            Self::RateLimited { .. } => Kind::RateLimited,
+            Self::Unavailable { .. } => Kind::Unavailable,
+            Self::AmbiguousVoice { .. } | Self::Rejected { .. } => Kind::Rejected,
+            Self::Network(_) => Kind::Network,
+            Self::InvalidResponse(_) => Kind::InvalidResponse,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Reqwest {
     client: reqwest::Client,
+    url: Url,
+    retry: Policy,
+    cache: Option<Cache>,
 }
 
 #[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
@@ -36,6 +196,59 @@ impl From<Voice> for String {
     }
 }
 
+impl Voice {
+    /// Whether this is already a voice ID, rather than a name that needs looking up
+    fn is_id(&self) -> bool {
+        self.0.len() == VOICE_ID_LENGTH && self.0.chars().all(|c| c.is_ascii_alphanumeric())
+    }
+}
+
+/// A voice the account can use, as the API lists it
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct VoiceDetails {
+    #[serde(rename = "voice_id")]
+    pub id: Voice,
+    pub name: String,
+    /// Such as "premade", "cloned" or "generated"
+    #[serde(default)]
+    pub category: Option<String>,
+    /// Descriptions such as accent, age or gender
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct VoicesBody {
+    voices: Vec<VoiceDetails>,
+}
+
+/// Find the voice that has `voice` as its ID, or failing that as its name, ignoring case
+fn find_voice(voice: Voice, voices: &[VoiceDetails]) -> Result<Voice, Error> {
+    if voices.iter().any(|details| details.id == voice) {
+        return Ok(voice);
+    }
+
+    let named: Vec<&VoiceDetails> = voices
+        .iter()
+        .filter(|details| details.name.eq_ignore_ascii_case(&voice.0))
+        .collect();
+    match named.as_slice() {
+        [details] => Ok(details.id.clone()),
+        [] => Err(Error::VoiceNotFound {
+            voice,
+            message: "no voice has that ID or name".to_string(),
+        }),
+        _ => Err(Error::AmbiguousVoice {
+            voice,
+            ids: named
+                .iter()
+                .map(|details| details.id.to_string())
+                .collect::<Vec<_>>()
+                .join(", "),
+        }),
+    }
+}
+
 #[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
 #[serde(transparent)]
 pub struct Key(String);
@@ -86,6 +299,101 @@ impl From<Message> for String {
     }
 }
 
+/// The model that reads, such as `eleven_multilingual_v2`
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Model(String);
+
+impl Model {
+    /// A model that can read `language`, the English only one for English
+    pub fn for_language(language: &Language) -> Self {
+        if language.matches(&Language::from("en".to_string())) {
+            Self::default()
+        } else {
+            Self(MULTILINGUAL_MODEL.to_string())
+        }
+    }
+}
+
+impl Default for Model {
+    fn default() -> Self {
+        Self(MONOLINGUAL_MODEL.to_string())
+    }
+}
+
+impl Display for Model {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<String> for Model {
+    fn from(v: String) -> Self {
+        Self(v)
+    }
+}
+
+impl From<Model> for String {
+    fn from(v: Model) -> Self {
+        v.0
+    }
+}
+
+/// Changes to how the voice sounds, each left to the voice's own setting when not given
+#[derive(Clone, Copy, Serialize, Debug, Default, PartialEq)]
+pub struct VoiceSettings {
+    /// From 0, more expressive, to 1, more consistent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stability: Option<f32>,
+    /// From 0 to 1, how closely to stick to the original voice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub similarity_boost: Option<f32>,
+    /// From 0 to 1, how much to exaggerate the style of the original voice
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub style: Option<f32>,
+    /// Sound more like the original speaker, at the cost of being a little slower
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub use_speaker_boost: Option<bool>,
+}
+
+impl VoiceSettings {
+    fn is_unset(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// How to read, sent along with every message
+#[derive(Clone, Serialize, Debug, Default, PartialEq)]
+pub struct Settings {
+    #[serde(rename = "model_id")]
+    pub model: Model,
+    #[serde(
+        rename = "voice_settings",
+        skip_serializing_if = "VoiceSettings::is_unset"
+    )]
+    pub voice: VoiceSettings,
+    /// Sent in the URL rather than the body
+    #[serde(skip)]
+    pub format: Format,
+}
+
+/// What is sent to be read
+#[derive(Serialize, Debug)]
+struct Speech<'a> {
+    text: &'a Message,
+    #[serde(flatten)]
+    settings: &'a Settings,
+}
+
+/// Everything that changes how a message sounds, which audio is cached by
+#[derive(Serialize, Debug)]
+struct Cached<'a> {
+    voice: &'a Voice,
+    output_format: String,
+    #[serde(flatten)]
+    speech: Speech<'a>,
+}
+
 #[async_trait]
 pub trait Repository<T: Audio> {
     async fn text_to_speech<
@@ -94,8 +402,36 @@ pub trait Repository<T: Audio> {
     >(
         &self,
         voice: V,
+        settings: &Settings,
         message: M,
     ) -> Result<T>;
+
+    /// Start synthesizing, returning the audio while it is still arriving so it can be played
+    /// before the whole message has been read
+    async fn stream_text_to_speech<
+        V: Into<Voice> + Debug + Sync + Send,
+        M: Into<Message> + Debug + Sync + Send,
+    >(
+        &self,
+        voice: V,
+        settings: &Settings,
+        message: M,
+    ) -> Result<Streaming>;
+
+    /// List the voices the account can use
+    async fn voices(&self) -> Result<Vec<VoiceDetails>>;
+
+    /// The ID of `voice`, which can be given as an ID or the name of a voice
+    async fn resolve_voice(&self, voice: Voice) -> Result<Voice> {
+        if voice.is_id() {
+            return Ok(voice);
+        }
+
+        let voices = self.voices().await?;
+        let id = find_voice(voice, &voices)?;
+        tracing::debug!("Using voice {}", id);
+        Ok(id)
+    }
 }
 
 #[async_trait]
@@ -107,39 +443,86 @@ impl Repository<VecU8A> for Reqwest {
     >(
         &self,
         voice: V,
+        settings: &Settings,
         message: M,
     ) -> Result<VecU8A> {
-        let client = &self.client;
-        let mut url =
-            Url::parse("https://api.elevenlabs.io/v1/text-to-speech").into_diagnostic()?;
-        url.path_segments_mut()
-            .expect("Infallible")
-            .extend(&[&String::from(voice.into())]);
+        let (voice, message) = (voice.into(), message.into());
+        let cached = self.cached(&voice, settings, &message)?;
+        if let Some((cache, key)) = &cached {
+            if let Some(audio) = cache.get(key).await {
+                return Ok(VecU8A::new(audio, settings.format));
+            }
+        }
 
-        let response = client
-            .post(url)
-            .header("accept", "audio/mpeg")
-            .json(&serde_json::json!({
-                "text": &message.into(),
-                "model_id": "eleven_monolingual_v1",
-            }))
-            .send()
+        let body = self
+            .request(voice, settings, message, &[])
+            .await?
+            .bytes()
             .await
-            .into_diagnostic()?;
-        if let Err(error) = response.error_for_status_ref() {
-            let error_body = response.text().await.into_diagnostic()?;
-            tracing::debug!("Failed to get audio {}", &error_body);
+            .map_err(Error::Network)?;
+        if let Some((cache, key)) = &cached {
+            cache.put(key, &body).await;
+        }
+        Ok(VecU8A::new(body.to_vec(), settings.format))
+    }
 
-            return Err(error).into_diagnostic();
+    #[instrument]
+    async fn stream_text_to_speech<
+        V: Into<Voice> + Debug + Sync + Send,
+        M: Into<Message> + Debug + Sync + Send,
+    >(
+        &self,
+        voice: V,
+        settings: &Settings,
+        message: M,
+    ) -> Result<Streaming> {
+        let (voice, message) = (voice.into(), message.into());
+        let cached = self.cached(&voice, settings, &message)?;
+        if let Some((cache, key)) = &cached {
+            if let Some(audio) = cache.get(key).await {
+                return Ok(Streaming::new(
+                    stream::iter([Ok::<_, String>(audio)]),
+                    settings.format,
+                ));
+            }
         }
 
-        let body = response
-            .error_for_status()
-            .into_diagnostic()?
-            .bytes()
+        let response = self.request(voice, settings, message, &["stream"]).await?;
+        Ok(match cached {
+            Some((cache, key)) => Streaming::new(
+                cache
+                    .clone()
+                    .put_as_it_arrives(key, response.bytes_stream()),
+                settings.format,
+            ),
+            None => Streaming::new(response.bytes_stream(), settings.format),
+        })
+    }
+
+    #[instrument]
+    async fn voices(&self) -> Result<Vec<VoiceDetails>> {
+        let url = self.endpoint(&["voices"])?;
+        let response = self
+            .retry
+            .send(self.client.get(url))
             .await
-            .into_diagnostic()?;
-        Ok(body.to_vec().into())
+            .map_err(Error::Network)?;
+        let status = response.status();
+        if !status.is_success() {
+            let error_body = response.text().await.map_err(Error::Network)?;
+            tracing::debug!("Failed to list voices {}", &error_body);
+
+            return Err(Error::from_response(None, status, &error_body).into());
+        }
+
+        let body: VoicesBody = response.json().await.map_err(|error| {
+            if error.is_decode() {
+                Error::InvalidResponse(error)
+            } else {
+                Error::Network(error)
+            }
+        })?;
+        Ok(body.voices)
     }
 }
 
@@ -148,7 +531,7 @@ impl Reqwest {
     // Instrument panic is false positive
     #[allow(clippy::panic_in_result_fn)]
     #[instrument]
-    pub fn try_new<T: Into<Key> + Debug>(key: T) -> Result<Self> {
+    pub fn try_new<T: Into<Key> + Debug>(key: T, url: Url, retry: Policy) -> Result<Self> {
         let mut headers = HeaderMap::new();
         let key = key.into().to_string();
         headers.insert("xi-api-key", key.try_into().into_diagnostic()?);
@@ -158,13 +541,178 @@ impl Reqwest {
             .build()
             .into_diagnostic()?;
 
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            url,
+            retry,
+            cache: None,
+        })
+    }
+
+    /// Keep audio in `cache`, and read it from there rather than asking for it again
+    #[must_use]
+    pub fn with_cache(mut self, cache: Option<Cache>) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// The cache and the key `message` read by `voice` with `settings` is cached under, when
+    /// there is a cache
+    fn cached(
+        &self,
+        voice: &Voice,
+        settings: &Settings,
+        message: &Message,
+    ) -> Result<Option<(&Cache, String)>> {
+        let Some(cache) = &self.cache else {
+            return Ok(None);
+        };
+
+        let request = serde_json::to_vec(&Cached {
+            voice,
+            output_format: settings.format.to_string(),
+            speech: Speech {
+                text: message,
+                settings,
+            },
+        })
+        .into_diagnostic()?;
+        Ok(Some((cache, Cache::key(&request))))
+    }
+
+    /// The API's URL with `path` appended
+    fn endpoint(&self, path: &[&str]) -> Result<Url> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|()| miette!("{} can't be used as the ElevenLabs API URL", self.url))?
+            .pop_if_empty()
+            .extend(path);
+        Ok(url)
+    }
+
+    /// Ask for `message` to be read by `voice` with `settings`, from the text to speech endpoint
+    /// with `path` appended
+    #[allow(
+        clippy::panic_in_result_fn,
+        reason = "The instrument macro is a false positive"
+    )]
+    #[instrument]
+    async fn request(
+        &self,
+        voice: Voice,
+        settings: &Settings,
+        message: Message,
+        path: &[&str],
+    ) -> Result<reqwest::Response> {
+        // WAV is asked for as the samples alone, and given its header when it is saved
+        let sent = match settings.format {
+            Format::Wav { sample_rate } => Format::Pcm { sample_rate },
+            format => format,
+        };
+        let voice_id = voice.to_string();
+        let url = self.endpoint(&[&["text-to-speech", &voice_id], path].concat())?;
+
+        let request = self
+            .client
+            .post(url)
+            .query(&[("output_format", sent.to_string())])
+            .header("accept", sent.mime_type())
+            .json(&Speech {
+                text: &message,
+                settings,
+            });
+        let response = self.retry.send(request).await.map_err(Error::Network)?;
+        let status = response.status();
+        if !status.is_success() {
+            let error_body = response.text().await.map_err(Error::Network)?;
+            tracing::debug!("Failed to get audio {}", &error_body);
+
+            return Err(Error::from_response(Some(voice), status, &error_body).into());
+        }
+
+        Ok(response)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::remote::elevenlabs::{Key, Message, Voice};
+    use reqwest::{StatusCode, Url};
+    use wiremock::{
+        matchers::{body_json, header, method, path, query_param},
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use crate::{
+        io::{
+            audio::{Audio, Format},
+            cache::Cache,
+        },
+        remote::{
+            elevenlabs::{
+                find_voice,
+                Error,
+                Key,
+                Message,
+                Model,
+                Repository,
+                Reqwest,
+                Settings,
+                Voice,
+                VoiceDetails,
+                VoiceSettings,
+                MONOLINGUAL_MODEL,
+                MULTILINGUAL_MODEL,
+            },
+            error::Kind,
+            retry::Policy,
+            translate::Language,
+        },
+    };
+
+    fn client(server: &MockServer) -> Reqwest {
+        let url = Url::parse(&format!("{}/v1", server.uri())).expect("Invalid URL");
+        Reqwest::try_new(Key::from("secret".to_string()), url, Policy::default())
+            .expect("Failed to create client")
+    }
+
+    async fn mount_voices(server: &MockServer) {
+        Mock::given(method("GET"))
+            .and(path("/v1/voices"))
+            .and(header("xi-api-key", "secret"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "voices": [
+                    {
+                        "voice_id": "21m00Tcm4TlvDq8ikWAM",
+                        "name": "Rachel",
+                        "category": "premade",
+                        "labels": {"accent": "american", "gender": "female"}
+                    },
+                    {
+                        "voice_id": "MF3mGyEYCl7XYWbV9V6O",
+                        "name": "Elli",
+                        "category": "premade",
+                        "labels": {}
+                    }
+                ]
+            })))
+            .mount(server)
+            .await;
+    }
+
+    fn details(id: &str, name: &str) -> VoiceDetails {
+        VoiceDetails {
+            id: Voice::from(id.to_string()),
+            name: name.to_string(),
+            category: None,
+            labels: std::collections::BTreeMap::new(),
+        }
+    }
+
+    fn error(status: StatusCode, body: &str) -> Error {
+        Error::from_response(Some(Voice::from("voice".to_string())), status, body)
+    }
 
     #[test]
     fn message_can_be_made_from_chatgpt_message() {
@@ -252,4 +800,274 @@ mod tests {
             "\"test\"".to_string()
         );
     }
+
+    #[test]
+    fn used_up_quotas_are_told_apart_from_bad_keys() {
+        let quota = error(
+            StatusCode::UNAUTHORIZED,
+            r#"{"detail": {"status": "quota_exceeded", "message": "This request exceeds your quota."}}"#,
+        );
+        let key = error(
+            StatusCode::UNAUTHORIZED,
+            r#"{"detail": {"status": "invalid_api_key", "message": "Invalid API key"}}"#,
+        );
+
+        assert_eq!(quota.kind(), Kind::QuotaExceeded);
+        assert_eq!(key.kind(), Kind::Unauthorized);
+        assert_eq!(
+            quota.to_string(),
+            "ElevenLabs quota is used up: This request exceeds your quota."
+        );
+    }
+
+    #[test]
+    fn missing_voices_are_not_found() {
+        let error = error(
+            StatusCode::BAD_REQUEST,
+            r#"{"detail": {"status": "voice_not_found", "message": "A voice with that ID does not exist."}}"#,
+        );
+
+        assert_eq!(error.kind(), Kind::NotFound);
+        assert_eq!(
+            error.to_string(),
+            "ElevenLabs has no voice voice: A voice with that ID does not exist."
+        );
+    }
+
+    #[test]
+    fn validation_errors_list_what_was_wrong() {
+        let error = error(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            r#"{"detail": [{"loc": ["body", "text"], "msg": "field required"}]}"#,
+        );
+
+        assert_eq!(error.kind(), Kind::Rejected);
+        assert_eq!(
+            error.to_string(),
+            "ElevenLabs rejected the request (422): field required"
+        );
+    }
+
+    #[test]
+    fn unparseable_bodies_are_kept_as_they_are() {
+        let error = error(StatusCode::BAD_GATEWAY, "Bad Gateway\n");
+
+        assert_eq!(error.kind(), Kind::Unavailable);
+        assert_eq!(
+            error.to_string(),
+            "ElevenLabs is unavailable (502): Bad Gateway"
+        );
+    }
+
+    #[tokio::test]
+    async fn voices_are_listed() {
+        let server = MockServer::start().await;
+        mount_voices(&server).await;
+
+        let voices = client(&server)
+            .voices()
+            .await
+            .expect("Failed to list voices");
+
+        assert_eq!(voices.len(), 2, "Expected both voices");
+        assert_eq!(voices[0].name, "Rachel");
+        assert_eq!(voices[0].category.as_deref(), Some("premade"));
+        assert_eq!(
+            voices[0].labels.get("accent").map(String::as_str),
+            Some("american")
+        );
+    }
+
+    #[tokio::test]
+    async fn names_are_resolved_to_ids() {
+        let server = MockServer::start().await;
+        mount_voices(&server).await;
+
+        let voice = client(&server)
+            .resolve_voice(Voice::from("rachel".to_string()))
+            .await
+            .expect("Failed to resolve the voice");
+
+        assert_eq!(voice.to_string(), "21m00Tcm4TlvDq8ikWAM");
+    }
+
+    #[tokio::test]
+    async fn ids_are_used_without_looking_them_up() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let voice = client(&server)
+            .resolve_voice(Voice::from("MF3mGyEYCl7XYWbV9V6O".to_string()))
+            .await
+            .expect("Failed to resolve the voice");
+
+        assert_eq!(voice.to_string(), "MF3mGyEYCl7XYWbV9V6O");
+    }
+
+    #[test]
+    fn unknown_names_are_not_found() {
+        let error = find_voice(
+            Voice::from("Nobody".to_string()),
+            &[details("21m00Tcm4TlvDq8ikWAM", "Rachel")],
+        )
+        .expect_err("Expected no voice to match");
+
+        assert_eq!(error.kind(), Kind::NotFound);
+    }
+
+    #[test]
+    fn shared_names_are_ambiguous() {
+        let error = find_voice(
+            Voice::from("Rachel".to_string()),
+            &[
+                details("21m00Tcm4TlvDq8ikWAM", "Rachel"),
+                details("AZnzlk1XvdvUeBnXmlld", "rachel"),
+            ],
+        )
+        .expect_err("Expected the name to be ambiguous");
+
+        assert!(
+            matches!(error, Error::AmbiguousVoice { .. }),
+            "Expected an ambiguous voice, got {error:?}"
+        );
+    }
+
+    #[test]
+    fn english_is_read_by_the_english_model() {
+        for (language, model) in [
+            ("en", MONOLINGUAL_MODEL),
+            ("en-GB", MONOLINGUAL_MODEL),
+            ("de", MULTILINGUAL_MODEL),
+            ("pt-BR", MULTILINGUAL_MODEL),
+        ] {
+            assert_eq!(
+                Model::for_language(&Language::from(language.to_string())).to_string(),
+                model,
+                "{language} should be read by {model}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn settings_are_sent_with_the_message() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/text-to-speech/MF3mGyEYCl7XYWbV9V6O"))
+            .and(body_json(serde_json::json!({
+                "text": "Hallo",
+                "model_id": MULTILINGUAL_MODEL,
+                "voice_settings": {"stability": 0.25, "use_speaker_boost": true}
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![1, 2, 3]))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let settings = Settings {
+            model: Model::from(MULTILINGUAL_MODEL.to_string()),
+            voice: VoiceSettings {
+                stability: Some(0.25),
+                use_speaker_boost: Some(true),
+                ..VoiceSettings::default()
+            },
+            ..Settings::default()
+        };
+        client(&server)
+            .text_to_speech(
+                Voice::from("MF3mGyEYCl7XYWbV9V6O".to_string()),
+                &settings,
+                Message::from("Hallo".to_string()),
+            )
+            .await
+            .expect("Failed to synthesize");
+    }
+
+    #[tokio::test]
+    async fn voice_settings_are_left_out_when_not_given() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/text-to-speech/MF3mGyEYCl7XYWbV9V6O"))
+            .and(body_json(serde_json::json!({
+                "text": "Hello",
+                "model_id": MONOLINGUAL_MODEL
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![1, 2, 3]))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        client(&server)
+            .text_to_speech(
+                Voice::from("MF3mGyEYCl7XYWbV9V6O".to_string()),
+                &Settings::default(),
+                Message::from("Hello".to_string()),
+            )
+            .await
+            .expect("Failed to synthesize");
+    }
+
+    #[tokio::test]
+    async fn wav_is_asked_for_as_samples() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/text-to-speech/MF3mGyEYCl7XYWbV9V6O"))
+            .and(query_param("output_format", "pcm_24000"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![0; 48000]))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let audio = client(&server)
+            .text_to_speech(
+                Voice::from("MF3mGyEYCl7XYWbV9V6O".to_string()),
+                &Settings {
+                    format: Format::Wav { sample_rate: 24000 },
+                    ..Settings::default()
+                },
+                Message::from("Hello".to_string()),
+            )
+            .await
+            .expect("Failed to synthesize");
+
+        assert_eq!(audio.format(), Format::Wav { sample_rate: 24000 });
+        assert_eq!(audio.duration(), std::time::Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn cached_audio_is_not_asked_for_again() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/text-to-speech/MF3mGyEYCl7XYWbV9V6O"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(b"audio".to_vec()))
+            .expect(2)
+            .mount(&server)
+            .await;
+        let tempdir = tempfile::tempdir().expect("Failed to create tempdir");
+        let client = client(&server).with_cache(Some(Cache::new(tempdir.path())));
+        let voice = || Voice::from("MF3mGyEYCl7XYWbV9V6O".to_string());
+        let stable = Settings {
+            voice: VoiceSettings {
+                stability: Some(1.0),
+                ..VoiceSettings::default()
+            },
+            ..Settings::default()
+        };
+
+        for settings in [&Settings::default(), &Settings::default(), &stable] {
+            let audio = client
+                .text_to_speech(voice(), settings, Message::from("Hello".to_string()))
+                .await
+                .expect("Failed to synthesize");
+            assert_eq!(audio.into_bytes(), b"audio", "Expected the same audio");
+        }
+        // The stream is read from the cache too, or the server would have been asked 3 times
+        client
+            .stream_text_to_speech(voice(), &stable, Message::from("Hello".to_string()))
+            .await
+            .expect("Failed to synthesize");
+    }
 }
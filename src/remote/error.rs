@@ -0,0 +1,106 @@
+use std::process::ExitCode;
+
+use reqwest::StatusCode;
+
+/// The broad kinds of failure talking to a remote service, each exiting with its own code so
+/// scripts can tell them apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// The key was wrong, missing or revoked
+    Unauthorized,
+    /// The account has used up what its plan allows
+    QuotaExceeded,
+    /// The voice, feed or other thing asked for doesn't exist
+    NotFound,
+    /// Still rate limited after every attempt
+    RateLimited,
+    /// The service is having trouble
+    Unavailable,
+    /// The service couldn't be reached, or didn't answer in time
+    Network,
+    /// The service said the request itself was wrong
+    Rejected,
+    /// The service answered with something that couldn't be understood
+    InvalidResponse,
+}
+
+impl Kind {
+    /// What a status that isn't a success usually means
+    pub fn of_status(status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Unauthorized,
+            StatusCode::NOT_FOUND | StatusCode::GONE => Self::NotFound,
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimited,
+            status if status.is_server_error() => Self::Unavailable,
+            _ => Self::Rejected,
+        }
+    }
+
+    /// 1 is left for anything else, and 2 for bad arguments
+    pub const fn exit_code(self) -> u8 {
+        match self {
+            Self::Unauthorized => 3,
+            Self::QuotaExceeded => 4,
+            Self::NotFound => 5,
+            Self::RateLimited => 6,
+            Self::Unavailable => 7,
+            Self::Network => 8,
+            Self::Rejected => 9,
+            Self::InvalidResponse => 10,
+        }
+    }
+}
+
+impl From<Kind> for ExitCode {
+    fn from(kind: Kind) -> Self {
+        Self::from(kind.exit_code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+
+    use super::Kind;
+
+    #[test]
+    fn statuses_are_classified() {
+        for (status, kind) in [
+            (StatusCode::UNAUTHORIZED, Kind::Unauthorized),
+            (StatusCode::FORBIDDEN, Kind::Unauthorized),
+            (StatusCode::NOT_FOUND, Kind::NotFound),
+            (StatusCode::TOO_MANY_REQUESTS, Kind::RateLimited),
+            (StatusCode::BAD_GATEWAY, Kind::Unavailable),
+            (StatusCode::UNPROCESSABLE_ENTITY, Kind::Rejected),
+        ] {
+            assert_eq!(Kind::of_status(status), kind, "{status} was misclassified");
+        }
+    }
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let kinds = [
+            Kind::Unauthorized,
+            Kind::QuotaExceeded,
+            Kind::NotFound,
+            Kind::RateLimited,
+            Kind::Unavailable,
+            Kind::Network,
+            Kind::Rejected,
+            Kind::InvalidResponse,
+        ];
+        let mut codes = kinds.map(Kind::exit_code).to_vec();
+        codes.sort_unstable();
+        codes.dedup();
+
+        assert_eq!(
+            codes.len(),
+            kinds.len(),
+            "Expected every kind to have its own code"
+        );
+        assert!(
+            codes.iter().all(|code| *code > 2),
+            "Expected 1 and 2 to be left for other failures"
+        );
+    }
+}
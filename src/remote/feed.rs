@@ -0,0 +1,461 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use miette::{Diagnostic, IntoDiagnostic, Result};
+use reqwest::{Response, Url};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::instrument;
+
+use super::{error::Kind, retry::Policy};
+
+#[derive(Debug)]
+pub struct Direct {
+    client: reqwest::Client,
+    retry: Policy,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Error {
+    #[error("There is no feed at {url}")]
+    #[diagnostic(
+        code(story_time::feed::not_found),
+        help("check --url is the address of the feed itself, rather than the site")
+    )]
+    NotFound { url: Url },
+
+    #[error("The feed at {url} is unavailable ({status})")]
+    #[diagnostic(code(story_time::feed::unavailable), help("try again later"))]
+    Unavailable { url: Url, status: u16 },
+
+    #[error("The feed at {url} couldn't be downloaded ({status})")]
+    #[diagnostic(code(story_time::feed::rejected))]
+    Rejected { url: Url, status: u16 },
+
+    #[error("Couldn't download the feed")]
+    #[diagnostic(
+        code(story_time::feed::network),
+        help("check the network connection, or try a longer --request-timeout")
+    )]
+    Network(#[source] reqwest::Error),
+
+    #[error("The feed couldn't be understood")]
+    #[diagnostic(
+        code(story_time::feed::invalid),
+        help("check --url is an RSS, Atom or JSON feed")
+    )]
+    Invalid(#[source] feed_rs::parser::ParseFeedError),
+
+    #[error("The feed couldn't be understood")]
+    #[diagnostic(code(story_time::feed::invalid_response))]
+    InvalidResponse(#[source] reqwest::Error),
+}
+
+impl Error {
+    /// Fail on responses that aren't a success, for both ways of fetching feeds
+    pub fn check(response: Response) -> Result<Response, Self> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+
+        let url = response.url().clone();
+        let status_code = status.as_u16();
+        Err(match Kind::of_status(status) {
+            Kind::NotFound => Self::NotFound { url },
+            Kind::Unavailable => Self::Unavailable {
+                url,
+                status: status_code,
+            },
+            _ => Self::Rejected {
+                url,
+                status: status_code,
+            },
+        })
+    }
+
+    pub const fn kind(&self) -> Kind {
+        match self {
+            Self::NotFound { .. } => Kind::NotFound,
+            Self::Unavailable { .. } => Kind::Unavailable,
+            Self::Rejected { .. } => Kind::Rejected,
+            Self::Network(_) => Kind::Network,
+            Self::Invalid(_) | Self::InvalidResponse(_) => Kind::InvalidResponse,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            Self::InvalidResponse(error)
+        } else {
+            Self::Network(error)
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct Feed {
+    pub title: Option<String>,
+    /// The language the feed says it is written in, such as "en-gb"
+    #[serde(default)]
+    pub language: Option<String>,
+    pub items: Vec<Item>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq)]
+pub struct Item {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub title: Option<String>,
+    pub time: Option<chrono::DateTime<Utc>>,
+    pub content: String,
+    #[serde(default)]
+    pub link: Option<String>,
+}
+
+#[async_trait]
+pub trait FeedSource {
+    async fn fetch(&self, url: &Url) -> Result<Feed>;
+}
+
+impl Feed {
+    /// Parse an RSS, Atom or JSON Feed document downloaded from `url`, which relative links are
+    /// resolved against
+    #[instrument(skip(source))]
+    pub fn parse(source: &[u8], url: &Url) -> Result<Self> {
+        let feed =
+            feed_rs::parser::parse_with_uri(source, Some(url.as_str())).map_err(Error::Invalid)?;
+        Ok(feed.into())
+    }
+}
+
+impl From<feed_rs::model::Feed> for Feed {
+    fn from(feed: feed_rs::model::Feed) -> Self {
+        Self {
+            title: feed.title.map(|title| title.content),
+            language: feed.language,
+            items: feed.entries.into_iter().map(Item::from).collect(),
+        }
+    }
+}
+
+impl From<feed_rs::model::Entry> for Item {
+    fn from(entry: feed_rs::model::Entry) -> Self {
+        let content = entry
+            .content
+            .and_then(|content| content.body)
+            .or_else(|| entry.summary.map(|summary| summary.content))
+            .unwrap_or_default();
+        // feed-rs makes up a random ID for entries that have no ID, link or title to derive one
+        // from, which would never match between runs
+        let id = (!entry.links.is_empty() || entry.title.is_some()).then_some(entry.id);
+        let link = entry
+            .links
+            .into_iter()
+            .find(|link| matches!(link.rel.as_deref(), None | Some("alternate")))
+            .map(|link| link.href);
+
+        Self {
+            id,
+            title: entry.title.map(|title| title.content),
+            time: entry.published.or(entry.updated),
+            content,
+            link,
+        }
+    }
+}
+
+impl Direct {
+    /// Create a client that downloads feeds straight from their origin
+    #[instrument]
+    pub fn try_new(retry: Policy) -> Result<Self> {
+        let client = reqwest::Client::builder().build().into_diagnostic()?;
+
+        Ok(Self { client, retry })
+    }
+}
+
+#[async_trait]
+impl FeedSource for Direct {
+    #[instrument]
+    async fn fetch(&self, url: &Url) -> Result<Feed> {
+        let response = self
+            .retry
+            .send(self.client.get(url.clone()))
+            .await
+            .map_err(Error::from)?;
+        let body = Error::check(response)?.bytes().await.map_err(Error::from)?;
+
+        Feed::parse(&body, url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use chrono::{DateTime, Utc};
+    use reqwest::Url;
+    use wiremock::{
+        matchers::{method, path},
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use super::{Direct, Error, Feed, FeedSource, Item};
+    use crate::remote::{error::Kind, retry::Policy};
+
+    fn once() -> Policy {
+        Policy {
+            max_attempts: NonZeroU32::MIN,
+            ..Policy::default()
+        }
+    }
+
+    fn url() -> Url {
+        Url::parse("https://example.com/feed.xml").expect("Invalid URL")
+    }
+
+    fn time(rfc3339: &str) -> DateTime<Utc> {
+        rfc3339.parse().expect("Invalid fixture date")
+    }
+
+    #[test]
+    fn parses_rss_0_91() {
+        let feed = Feed::parse(
+            include_bytes!("../../tests/fixtures/feeds/rss-0.91.xml"),
+            &url(),
+        )
+        .expect("Failed to parse feed");
+
+        assert_eq!(
+            feed,
+            Feed {
+                title: Some("Story Time Weekly".to_string()),
+                language: Some("en-gb".to_string()),
+                items: vec![
+                    Item {
+                        id: Some("b253f0be28791a55f1acdc6df1fad5".to_string()),
+                        title: Some("The Fox".to_string()),
+                        time: None,
+                        content: "A quick brown fox jumps over the lazy dog.".to_string(),
+                        link: Some("https://example.com/fox".to_string()),
+                    },
+                    Item {
+                        id: Some("a78b14deaa46f14fc487aa72c45ef752".to_string()),
+                        title: Some("The Owl".to_string()),
+                        time: None,
+                        content: "An owl watches from the old oak tree.".to_string(),
+                        link: Some("https://example.com/owl".to_string()),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_rss_2_0() {
+        let feed = Feed::parse(
+            include_bytes!("../../tests/fixtures/feeds/rss-2.0.xml"),
+            &url(),
+        )
+        .expect("Failed to parse feed");
+
+        assert_eq!(
+            feed,
+            Feed {
+                title: Some("Story Time Daily".to_string()),
+                language: Some("en-gb".to_string()),
+                items: vec![
+                    Item {
+                        id: Some("https://example.com/fox".to_string()),
+                        title: Some("The Fox".to_string()),
+                        time: Some(time("2023-09-25T08:30:00Z")),
+                        content: "<p>A quick brown fox jumps over the lazy dog.</p>".to_string(),
+                        link: Some("https://example.com/fox".to_string()),
+                    },
+                    Item {
+                        id: Some("https://example.com/owl".to_string()),
+                        title: Some("The Owl".to_string()),
+                        time: Some(time("2023-09-24T20:00:00Z")),
+                        content: "An owl watches from the old oak tree.".to_string(),
+                        link: Some("https://example.com/owl".to_string()),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_atom() {
+        let feed = Feed::parse(
+            include_bytes!("../../tests/fixtures/feeds/atom.xml"),
+            &url(),
+        )
+        .expect("Failed to parse feed");
+
+        assert_eq!(
+            feed,
+            Feed {
+                title: Some("Story Time Atom".to_string()),
+                language: None,
+                items: vec![
+                    Item {
+                        id: Some("urn:uuid:1225c695-cfb8-4ebb-aaaa-80da344efa6a".to_string()),
+                        title: Some("The Fox".to_string()),
+                        time: Some(time("2023-09-25T08:30:00Z")),
+                        content: "<p>A quick brown fox jumps over the lazy dog.</p>".to_string(),
+                        link: Some("https://example.com/fox".to_string()),
+                    },
+                    Item {
+                        id: Some("urn:uuid:2225c695-cfb8-4ebb-aaaa-80da344efa6b".to_string()),
+                        title: Some("The Owl".to_string()),
+                        time: Some(time("2023-09-24T20:00:00Z")),
+                        content: "An owl watches from the old oak tree.".to_string(),
+                        link: Some("https://example.com/owl".to_string()),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parses_json_feed() {
+        let feed = Feed::parse(
+            include_bytes!("../../tests/fixtures/feeds/feed.json"),
+            &url(),
+        )
+        .expect("Failed to parse feed");
+
+        assert_eq!(
+            feed,
+            Feed {
+                title: Some("Story Time JSON".to_string()),
+                language: None,
+                items: vec![
+                    Item {
+                        id: Some("https://example.com/fox".to_string()),
+                        title: Some("The Fox".to_string()),
+                        time: Some(time("2023-09-25T08:30:00Z")),
+                        content: "<p>A quick brown fox jumps over the lazy dog.</p>".to_string(),
+                        link: Some("https://example.com/fox".to_string()),
+                    },
+                    Item {
+                        id: Some("https://example.com/owl".to_string()),
+                        title: Some("The Owl".to_string()),
+                        time: None,
+                        content: "An owl watches from the old oak tree.".to_string(),
+                        link: Some("https://example.com/owl".to_string()),
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn entries_without_links_or_titles_have_no_id() {
+        let feed = Feed::parse(
+            br#"{
+                "version": "https://jsonfeed.org/version/1.1",
+                "title": "Story Time JSON",
+                "items": [{"id": "", "content_text": "An untitled story."}]
+            }"#,
+            &url(),
+        )
+        .expect("Failed to parse feed");
+
+        assert_eq!(feed.items[0].id, None);
+    }
+
+    #[test]
+    fn entries_without_links_keep_a_stable_id() {
+        let source = include_bytes!("../../tests/fixtures/feeds/rss-2.0-without-links.xml");
+        let feed = Feed::parse(source, &url()).expect("Failed to parse feed");
+
+        assert_eq!(
+            feed.items
+                .iter()
+                .map(|item| item.id.as_deref())
+                .collect::<Vec<_>>(),
+            vec![
+                Some("bedtime-0042"),
+                Some("35ea2e33d7bca4cc6283159e7dbb5078")
+            ],
+            "Expected the GUID to be kept, and an ID made from the feed URL and title otherwise"
+        );
+    }
+
+    #[test]
+    fn rejects_documents_that_are_not_feeds() {
+        assert!(
+            Feed::parse(b"<html><body>Not a feed</body></html>", &url()).is_err(),
+            "Expected HTML to be rejected"
+        );
+    }
+
+    #[tokio::test]
+    async fn direct_fetches_the_feed_from_its_origin() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/feed.xml"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_bytes(
+                    include_bytes!("../../tests/fixtures/feeds/rss-2.0.xml").to_vec(),
+                ),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url = Url::parse(&format!("{}/feed.xml", server.uri())).expect("Invalid URL");
+        let feed = Direct::try_new(Policy::default())
+            .expect("Failed to create client")
+            .fetch(&url)
+            .await
+            .expect("Failed to fetch feed");
+
+        assert_eq!(feed.title, Some("Story Time Daily".to_string()));
+    }
+
+    #[tokio::test]
+    async fn direct_fails_when_the_origin_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let url = Url::parse(&format!("{}/feed.xml", server.uri())).expect("Invalid URL");
+        let result = Direct::try_new(once())
+            .expect("Failed to create client")
+            .fetch(&url)
+            .await;
+
+        assert!(result.is_err(), "Expected a server error to fail the fetch");
+    }
+
+    #[tokio::test]
+    async fn missing_feeds_are_not_found() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .mount(&server)
+            .await;
+
+        let url = Url::parse(&format!("{}/feed.xml", server.uri())).expect("Invalid URL");
+        let error = Direct::try_new(once())
+            .expect("Failed to create client")
+            .fetch(&url)
+            .await
+            .expect_err("Expected a missing feed to fail the fetch");
+
+        assert_eq!(
+            error.downcast_ref::<Error>().map(Error::kind),
+            Some(Kind::NotFound)
+        );
+    }
+}
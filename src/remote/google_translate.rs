@@ -0,0 +1,429 @@
+use std::fmt::{Debug, Display, Formatter};
+
+use async_trait::async_trait;
+use miette::{Diagnostic, IntoDiagnostic, Result};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::instrument;
+
+use super::{
+    error::Kind,
+    retry::Policy,
+    translate::{Language, Translation, Translator},
+};
+
+pub const DEFAULT_URL: &str = "https://translation.googleapis.com/language/translate/v2";
+
+/// Translate with version 2 of the Google Cloud Translation API
+#[derive(Debug)]
+pub struct GoogleTranslate {
+    client: reqwest::Client,
+    key: Key,
+    url: Url,
+    retry: Policy,
+}
+
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Key(String);
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<String> for Key {
+    fn from(v: String) -> Self {
+        Self(v)
+    }
+}
+
+impl From<Key> for String {
+    fn from(v: Key) -> Self {
+        v.0
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum Error {
+    #[error("Google Translate didn't accept the key: {message}")]
+    #[diagnostic(
+        code(story_time::google_translate::unauthorized),
+        help("check GOOGLE_TRANSLATE_KEY is an API key with the Cloud Translation API enabled")
+    )]
+    Unauthorized { message: String },
+
+    #[error("Google Translate quota is used up: {message}")]
+    #[diagnostic(
+        code(story_time::google_translate::quota_exceeded),
+        help("wait for the quota to reset, or raise it in the Google Cloud console")
+    )]
+    QuotaExceeded { message: String },
+
+    #[error("Google Translate is still rate limiting requests: {message}")]
+    #[diagnostic(
+        code(story_time::google_translate::rate_limited),
+        help("try a lower --concurrency, or more --max-attempts")
+    )]
+    RateLimited { message: String },
+
+    #[error("Google Translate is unavailable ({status}): {message}")]
+    #[diagnostic(
+        code(story_time::google_translate::unavailable),
+        help("try again later")
+    )]
+    Unavailable { status: u16, message: String },
+
+    #[error("Google Translate rejected the request ({status}): {message}")]
+    #[diagnostic(
+        code(story_time::google_translate::rejected),
+        help("check --target-language is a language Google Translate supports")
+    )]
+    Rejected { status: u16, message: String },
+
+    #[error("Couldn't reach Google Translate")]
+    #[diagnostic(
+        code(story_time::google_translate::network),
+        help("check the network connection, or try a longer --request-timeout")
+    )]
+    Network(#[source] reqwest::Error),
+
+    #[error("Google Translate's answer couldn't be understood")]
+    #[diagnostic(code(story_time::google_translate::invalid_response))]
+    InvalidResponse(#[source] reqwest::Error),
+}
+
+/// The body sent with an error status
+#[derive(Deserialize, Debug)]
+struct ErrorBody {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorDetail {
+    message: String,
+    #[serde(default)]
+    errors: Vec<ErrorReason>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ErrorReason {
+    reason: String,
+}
+
+impl Error {
+    /// Work out what went wrong from an error status and the body that came with it
+    fn from_response(status: StatusCode, body: &str) -> Self {
+        let (message, reasons) = match serde_json::from_str::<ErrorBody>(body) {
+            Ok(ErrorBody { error }) => (
+                error.message,
+                error
+                    .errors
+                    .into_iter()
+                    .map(|reason| reason.reason)
+                    .collect(),
+            ),
+            Err(_) if body.trim().is_empty() => (status.to_string(), vec![]),
+            Err(_) => (body.trim().to_string(), vec![]),
+        };
+        let has_reason = |wanted: &[&str]| {
+            reasons
+                .iter()
+                .any(|reason| wanted.contains(&reason.as_str()))
+        };
+
+        // Google uses 403 for used up quotas too, and 400 for keys that don't exist
+        if has_reason(&["dailyLimitExceeded", "quotaExceeded"]) {
+            return Self::QuotaExceeded { message };
+        }
+        if has_reason(&["rateLimitExceeded", "userRateLimitExceeded"]) {
+            return Self::RateLimited { message };
+        }
+        if message.contains("API key") {
+            return Self::Unauthorized { message };
+        }
+
+        match Kind::of_status(status) {
+            Kind::Unauthorized => Self::Unauthorized { message },
+            Kind::RateLimited => Self::RateLimited { message },
+            Kind::Unavailable => Self::Unavailable {
+                status: status.as_u16(),
+                message,
+            },
+            _ => Self::Rejected {
+                status: status.as_u16(),
+                message,
+            },
+        }
+    }
+
+    pub const fn kind(&self) -> Kind {
+        match self {
+            Self::Unauthorized { .. } => Kind::Unauthorized,
+            Self::QuotaExceeded { .. } => Kind::QuotaExceeded,
+            Self::RateLimited { .. } => Kind::RateLimited,
+            Self::Unavailable { .. } => Kind::Unavailable,
+            Self::Rejected { .. } => Kind::Rejected,
+            Self::Network(_) => Kind::Network,
+            Self::InvalidResponse(_) => Kind::InvalidResponse,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(error: reqwest::Error) -> Self {
+        if error.is_decode() {
+            Self::InvalidResponse(error)
+        } else {
+            Self::Network(error)
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Request {
+    q: Vec<String>,
+    target: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Response {
+    data: Translations,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct Translations {
+    translations: Vec<GoogleTranslation>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct GoogleTranslation {
+    translated_text: String,
+    detected_source_language: Option<String>,
+}
+
+impl GoogleTranslate {
+    /// Create a client for the translation endpoint at `url`, usually [`DEFAULT_URL`]
+    #[instrument]
+    pub fn try_new<T: Into<Key> + Debug>(key: T, url: Url, retry: Policy) -> Result<Self> {
+        let client = reqwest::Client::builder().build().into_diagnostic()?;
+
+        Ok(Self {
+            client,
+            key: key.into(),
+            url,
+            retry,
+        })
+    }
+}
+
+#[async_trait]
+impl Translator for GoogleTranslate {
+    #[instrument]
+    async fn translate<
+        T: Into<String> + Debug + Sync + Send,
+        L: Into<Language> + Debug + Sync + Send,
+    >(
+        &self,
+        text: T,
+        target: L,
+    ) -> Result<Translation> {
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .append_pair("key", &self.key.to_string());
+
+        let request = self.client.post(url).json(&Request {
+            q: vec![text.into()],
+            target: target.into().into(),
+        });
+        let response = self.retry.send(request).await.map_err(Error::from)?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.map_err(Error::from)?;
+            return Err(Error::from_response(status, &body).into());
+        }
+        let response: Response = response.json().await.map_err(Error::from)?;
+
+        let detected_source_language = response
+            .data
+            .translations
+            .iter()
+            .find_map(|translation| translation.detected_source_language.clone())
+            .map(Language::from);
+        let text = response
+            .data
+            .translations
+            .into_iter()
+            .map(|translation| translation.translated_text)
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        Ok(Translation {
+            text,
+            detected_source_language,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::{StatusCode, Url};
+    use wiremock::{
+        matchers::{body_json, method, path, query_param},
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use super::{Error, GoogleTranslate, Key};
+    use crate::remote::{
+        error::Kind,
+        retry::Policy,
+        translate::{Language, Translation, Translator},
+    };
+
+    fn translator(server: &MockServer) -> GoogleTranslate {
+        let url =
+            Url::parse(&format!("{}/language/translate/v2", server.uri())).expect("Invalid URL");
+        GoogleTranslate::try_new(Key::from("secret".to_string()), url, Policy::default())
+            .expect("Failed to create client")
+    }
+
+    #[test]
+    fn key_implements_display() {
+        let key = Key("test".to_string());
+        assert_eq!(key.to_string(), "test");
+    }
+
+    #[test]
+    fn key_implements_into_string() {
+        let key: String = Key("test".to_string()).into();
+        assert_eq!(key, "test".to_string());
+    }
+
+    #[tokio::test]
+    async fn translate_sends_the_text_and_key() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/language/translate/v2"))
+            .and(query_param("key", "secret"))
+            .and(body_json(serde_json::json!({
+                "q": ["Der schnelle braune Fuchs."],
+                "target": "en"
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": {
+                    "translations": [
+                        {
+                            "translatedText": "The quick brown fox.",
+                            "detectedSourceLanguage": "de"
+                        }
+                    ]
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let translation = translator(&server)
+            .translate(
+                "Der schnelle braune Fuchs.",
+                Language::from("en".to_string()),
+            )
+            .await
+            .expect("Failed to translate");
+
+        assert_eq!(
+            translation,
+            Translation {
+                text: "The quick brown fox.".to_string(),
+                detected_source_language: Some(Language::from("de".to_string())),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn translate_fails_on_an_error_status() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(403))
+            .mount(&server)
+            .await;
+
+        let result = translator(&server)
+            .translate(
+                "Der schnelle braune Fuchs.",
+                Language::from("en".to_string()),
+            )
+            .await;
+
+        assert!(result.is_err(), "Expected a 403 to be an error");
+    }
+
+    #[tokio::test]
+    async fn translate_tries_again_when_rate_limited() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "0"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": {
+                    "translations": [{ "translatedText": "The quick brown fox." }]
+                }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let translation = translator(&server)
+            .translate(
+                "Der schnelle braune Fuchs.",
+                Language::from("en".to_string()),
+            )
+            .await
+            .expect("Failed to translate");
+
+        assert_eq!(translation.text, "The quick brown fox.");
+    }
+
+    #[test]
+    fn keys_that_do_not_exist_are_unauthorized() {
+        let error = Error::from_response(
+            StatusCode::BAD_REQUEST,
+            r#"{"error": {"code": 400, "message": "API key not valid. Please pass a valid API key.", "errors": [{"reason": "badRequest"}]}}"#,
+        );
+
+        assert_eq!(error.kind(), Kind::Unauthorized);
+        assert_eq!(
+            error.to_string(),
+            "Google Translate didn't accept the key: API key not valid. Please pass a valid API key."
+        );
+    }
+
+    #[test]
+    fn used_up_quotas_are_told_apart_from_bad_keys() {
+        let error = Error::from_response(
+            StatusCode::FORBIDDEN,
+            r#"{"error": {"code": 403, "message": "Daily Limit Exceeded", "errors": [{"reason": "dailyLimitExceeded"}]}}"#,
+        );
+
+        assert_eq!(error.kind(), Kind::QuotaExceeded);
+    }
+
+    #[test]
+    fn unsupported_languages_are_rejected() {
+        let error = Error::from_response(
+            StatusCode::BAD_REQUEST,
+            r#"{"error": {"code": 400, "message": "Invalid Value", "errors": [{"reason": "invalid"}]}}"#,
+        );
+
+        assert_eq!(error.kind(), Kind::Rejected);
+    }
+}
@@ -1,3 +1,12 @@
 pub mod chatgpt;
+pub mod deepl;
 pub mod elevenlabs;
+pub mod error;
+pub mod feed;
+pub mod google_translate;
 pub mod morss;
+pub mod openai;
+pub mod readability;
+pub mod retry;
+pub mod translate;
+pub mod tts;
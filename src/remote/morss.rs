@@ -1 +1,184 @@
+use std::fmt::Debug;
 
+use async_trait::async_trait;
+use miette::{miette, IntoDiagnostic, Result};
+use reqwest::Url;
+use tracing::instrument;
+
+use super::{
+    feed::{Error, Feed, FeedSource},
+    retry::Policy,
+};
+
+pub const DEFAULT_HOST: &str = "https://morss.it/";
+
+/// Fetch feeds through a [morss](https://morss.it/) instance, which converts them to JSON
+#[derive(Debug)]
+pub struct Morss {
+    client: reqwest::Client,
+    host: Url,
+    retry: Policy,
+}
+
+impl Morss {
+    /// Create a client for the morss instance at `host`
+    #[instrument]
+    pub fn try_new(host: Url, retry: Policy) -> Result<Self> {
+        if host.cannot_be_a_base() {
+            return Err(miette!("{} cannot be used as a morss host", host));
+        }
+
+        let client = reqwest::Client::builder().build().into_diagnostic()?;
+
+        Ok(Self {
+            client,
+            host,
+            retry,
+        })
+    }
+
+    /// The morss URL that proxies `url`
+    pub fn url_for(&self, url: &Url) -> Result<Url> {
+        let original_host = url.host_str().ok_or_else(|| miette!("No host"))?;
+        let original_host = url.port().map_or_else(
+            || original_host.to_string(),
+            |port| format!("{original_host}:{port}"),
+        );
+
+        let mut morss_url = self.host.clone();
+        morss_url
+            .path_segments_mut()
+            .map_err(|()| miette!("{} cannot be used as a morss host", self.host))?
+            .pop_if_empty()
+            .extend(&[":format=json:cors", &original_host])
+            .extend(url.path_segments().into_iter().flatten());
+        morss_url.set_query(url.query());
+
+        Ok(morss_url)
+    }
+}
+
+#[async_trait]
+impl FeedSource for Morss {
+    #[instrument]
+    async fn fetch(&self, url: &Url) -> Result<Feed> {
+        let response = self
+            .retry
+            .send(self.client.get(self.url_for(url)?))
+            .await
+            .map_err(Error::from)?;
+
+        Ok(Error::check(response)?.json().await.map_err(Error::from)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use wiremock::{
+        matchers::{method, path, query_param},
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use super::{Morss, DEFAULT_HOST};
+    use crate::remote::{
+        feed::{Feed, FeedSource, Item},
+        retry::Policy,
+    };
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).expect("Invalid URL")
+    }
+
+    #[test]
+    fn url_for_rewrites_to_the_default_host() {
+        let morss =
+            Morss::try_new(url(DEFAULT_HOST), Policy::default()).expect("Failed to create client");
+
+        assert_eq!(
+            morss
+                .url_for(&url("https://example.com/blog/feed.xml?tag=stories"))
+                .expect("Failed to build URL"),
+            url("https://morss.it/:format=json:cors/example.com/blog/feed.xml?tag=stories")
+        );
+    }
+
+    #[test]
+    fn url_for_keeps_the_port_of_the_original_feed() {
+        let morss =
+            Morss::try_new(url(DEFAULT_HOST), Policy::default()).expect("Failed to create client");
+
+        assert_eq!(
+            morss
+                .url_for(&url("http://intranet:8080/feed"))
+                .expect("Failed to build URL"),
+            url("https://morss.it/:format=json:cors/intranet:8080/feed")
+        );
+    }
+
+    #[test]
+    fn url_for_keeps_the_path_of_a_self_hosted_instance() {
+        let morss = Morss::try_new(url("http://localhost:8000/morss/"), Policy::default())
+            .expect("Failed to create client");
+
+        assert_eq!(
+            morss
+                .url_for(&url("https://example.com/feed.xml"))
+                .expect("Failed to build URL"),
+            url("http://localhost:8000/morss/:format=json:cors/example.com/feed.xml")
+        );
+    }
+
+    #[test]
+    fn hosts_that_cannot_be_a_base_are_rejected() {
+        assert!(
+            Morss::try_new(url("mailto:someone@example.com"), Policy::default()).is_err(),
+            "Expected a mailto URL to be rejected"
+        );
+    }
+
+    #[tokio::test]
+    async fn fetch_requests_the_feed_through_morss() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/:format=json:cors/example.com/feed.xml"))
+            .and(query_param("tag", "stories"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "title": "Story Time Daily",
+                "items": [
+                    {
+                        "title": "The Fox",
+                        "time": "2023-09-25T08:30:00Z",
+                        "content": "A quick brown fox jumps over the lazy dog.",
+                        "link": "https://example.com/fox"
+                    }
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let feed = Morss::try_new(url(&server.uri()), Policy::default())
+            .expect("Failed to create client")
+            .fetch(&url("https://example.com/feed.xml?tag=stories"))
+            .await
+            .expect("Failed to fetch feed");
+
+        assert_eq!(
+            feed,
+            Feed {
+                title: Some("Story Time Daily".to_string()),
+                language: None,
+                items: vec![Item {
+                    id: None,
+                    title: Some("The Fox".to_string()),
+                    time: Some("2023-09-25T08:30:00Z".parse().expect("Invalid date")),
+                    content: "A quick brown fox jumps over the lazy dog.".to_string(),
+                    link: Some("https://example.com/fox".to_string()),
+                }],
+            }
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use miette::{miette, IntoDiagnostic, Result};
+use reqwest::{header::HeaderMap, Url};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::{
+    chatgpt::{Direction, Error, GenerationOptions, Key, Message, Model, Prompt, Repository, Turn},
+    retry::Policy,
+};
+
+pub const DEFAULT_URL: &str = "https://api.openai.com/v1";
+
+/// Generate text with any server that speaks the `OpenAI` chat completions API, such as `OpenAI`
+/// itself, Azure `OpenAI`, or a local `llama.cpp` or `Ollama` server
+#[derive(Debug)]
+pub struct OpenAi {
+    client: reqwest::Client,
+    url: Url,
+    retry: Policy,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Request<'a> {
+    model: &'a Model,
+    messages: Vec<ChatMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct ChatMessage {
+    role: String,
+    content: String,
+}
+
+impl ChatMessage {
+    fn new(role: &str, content: String) -> Self {
+        Self {
+            role: role.to_string(),
+            content,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Response {
+    choices: Vec<Choice>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Choice {
+    message: ChatMessage,
+}
+
+impl OpenAi {
+    /// Create a client for the API at `url`, such as [`DEFAULT_URL`], sending `key` when there is
+    /// one, as local servers usually don't need it
+    #[allow(
+        clippy::panic_in_result_fn,
+        reason = "The instrument macro is a false positive"
+    )]
+    #[instrument]
+    pub fn try_new(key: Option<Key>, url: Url, retry: Policy) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        if let Some(key) = key {
+            headers.insert(
+                "Authorization",
+                format!("Bearer {key}").try_into().into_diagnostic()?,
+            );
+        }
+
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .into_diagnostic()?;
+
+        Ok(Self { client, url, retry })
+    }
+
+    /// The API's URL with `path` appended
+    fn endpoint(&self, path: &[&str]) -> Result<Url> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|()| miette!("{} can't be used as the API URL", self.url))?
+            .pop_if_empty()
+            .extend(path);
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl Repository for OpenAi {
+    #[instrument]
+    async fn continue_conversation<P: Into<Prompt> + Debug + Sync + Send>(
+        &self,
+        direction: &Direction,
+        history: &[Turn],
+        prompt: P,
+        options: &GenerationOptions,
+    ) -> Result<Message> {
+        let mut messages = vec![ChatMessage::new("system", direction.to_string())];
+        for turn in history {
+            messages.push(ChatMessage::new("user", turn.prompt.to_string()));
+            messages.push(ChatMessage::new("assistant", turn.message.to_string()));
+        }
+        messages.push(ChatMessage::new("user", prompt.into().into()));
+
+        let model = options.model.clone().unwrap_or_default();
+        let request = self
+            .client
+            .post(self.endpoint(&["chat", "completions"])?)
+            .json(&Request {
+                model: &model,
+                messages,
+                temperature: options.temperature,
+                top_p: options.top_p,
+                max_tokens: options.max_output_tokens,
+                seed: options.seed,
+            });
+        let response = self.retry.send(request).await.map_err(Error::Network)?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.map_err(Error::Network)?;
+            tracing::debug!("Failed to generate text {}", &body);
+
+            return Err(Error::from_response(status, &body).into());
+        }
+
+        let response: Response = response.json().await.map_err(|error| {
+            if error.is_decode() {
+                Error::InvalidResponse {
+                    message: error.to_string(),
+                }
+            } else {
+                Error::Network(error)
+            }
+        })?;
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| Error::InvalidResponse {
+                message: "No choices were given".to_string(),
+            })?;
+        Ok(choice.message.content.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use wiremock::{
+        matchers::{body_json, header, method, path},
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use super::OpenAi;
+    use crate::remote::{
+        chatgpt::{
+            Direction,
+            Error,
+            GenerationOptions,
+            Key,
+            Message,
+            Model,
+            Prompt,
+            Repository,
+            Turn,
+        },
+        error::Kind,
+        retry::Policy,
+    };
+
+    fn generator(server: &MockServer, key: Option<Key>) -> OpenAi {
+        let url = Url::parse(&format!("{}/v1", server.uri())).expect("Invalid URL");
+        OpenAi::try_new(key, url, Policy::default()).expect("Failed to create client")
+    }
+
+    #[tokio::test]
+    async fn the_direction_and_prompt_are_sent_to_the_model() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(header("Authorization", "Bearer secret"))
+            .and(body_json(serde_json::json!({
+                "model": "gpt-3.5-turbo",
+                "messages": [
+                    {"role": "system", "content": "You are reading aloud"},
+                    {"role": "user", "content": "A story about a fox"}
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-1",
+                "choices": [
+                    {
+                        "index": 0,
+                        "message": {"role": "assistant", "content": "Once upon a time"},
+                        "finish_reason": "stop"
+                    }
+                ]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let message = generator(&server, Some(Key::from("secret".to_string())))
+            .generate_text(
+                Direction::from("You are reading aloud".to_string()),
+                Prompt::from("A story about a fox".to_string()),
+                &GenerationOptions::default(),
+            )
+            .await
+            .expect("Failed to generate");
+
+        assert_eq!(message, Message::from("Once upon a time".to_string()));
+    }
+
+    #[tokio::test]
+    async fn no_key_is_sent_without_one() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("Authorization", "Bearer secret"))
+            .respond_with(ResponseTemplate::new(401))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "choices": [{"message": {"role": "assistant", "content": "Once upon a time"}}]
+            })))
+            .mount(&server)
+            .await;
+
+        let message = generator(&server, None)
+            .generate_text(
+                Direction::from("You are reading aloud".to_string()),
+                Prompt::from("A story about a fox".to_string()),
+                &GenerationOptions::default(),
+            )
+            .await
+            .expect("Failed to generate");
+
+        assert_eq!(message, Message::from("Once upon a time".to_string()));
+    }
+
+    #[tokio::test]
+    async fn bad_keys_are_unauthorized() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(401).set_body_json(serde_json::json!({
+                "error": {
+                    "message": "Incorrect API key provided",
+                    "type": "invalid_request_error",
+                    "code": "invalid_api_key"
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        let error = generator(&server, Some(Key::from("wrong".to_string())))
+            .generate_text(
+                Direction::from("You are reading aloud".to_string()),
+                Prompt::from("A story about a fox".to_string()),
+                &GenerationOptions::default(),
+            )
+            .await
+            .expect_err("Expected a 401 to be an error");
+
+        let error = error
+            .downcast_ref::<Error>()
+            .expect("Expected a ChatGPT error");
+        assert_eq!(error.kind(), Kind::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn answers_without_choices_are_invalid() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({ "choices": [] })),
+            )
+            .mount(&server)
+            .await;
+
+        let error = generator(&server, None)
+            .generate_text(
+                Direction::from("You are reading aloud".to_string()),
+                Prompt::from("A story about a fox".to_string()),
+                &GenerationOptions::default(),
+            )
+            .await
+            .expect_err("Expected no choices to be an error");
+
+        let error = error
+            .downcast_ref::<Error>()
+            .expect("Expected a ChatGPT error");
+        assert_eq!(error.kind(), Kind::InvalidResponse);
+    }
+
+    #[tokio::test]
+    async fn options_are_sent_with_the_messages() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_json(serde_json::json!({
+                "model": "llama3",
+                "messages": [
+                    {"role": "system", "content": "You are reading aloud"},
+                    {"role": "user", "content": "A story about a fox"}
+                ],
+                "temperature": 0.5,
+                "top_p": 0.25,
+                "max_tokens": 500,
+                "seed": 42
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "choices": [{"message": {"role": "assistant", "content": "Once upon a time"}}]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        generator(&server, None)
+            .generate_text(
+                Direction::from("You are reading aloud".to_string()),
+                Prompt::from("A story about a fox".to_string()),
+                &GenerationOptions {
+                    model: Some(Model::from("llama3".to_string())),
+                    temperature: Some(0.5),
+                    top_p: Some(0.25),
+                    max_output_tokens: Some(500),
+                    seed: Some(42),
+                },
+            )
+            .await
+            .expect("Failed to generate");
+    }
+
+    #[tokio::test]
+    async fn earlier_turns_are_sent_before_the_prompt() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_json(serde_json::json!({
+                "model": "gpt-3.5-turbo",
+                "messages": [
+                    {"role": "system", "content": "You are reading aloud"},
+                    {"role": "user", "content": "A story about a fox"},
+                    {"role": "assistant", "content": "Once upon a time"},
+                    {"role": "user", "content": "Continue the story"}
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "choices": [{"message": {"role": "assistant", "content": "The next day"}}]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let message = generator(&server, None)
+            .continue_conversation(
+                &Direction::from("You are reading aloud".to_string()),
+                &[Turn {
+                    prompt: Prompt::from("A story about a fox".to_string()),
+                    message: Message::from("Once upon a time".to_string()),
+                }],
+                Prompt::from("Continue the story".to_string()),
+                &GenerationOptions::default(),
+            )
+            .await
+            .expect("Failed to generate");
+
+        assert_eq!(message, Message::from("The next day".to_string()));
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Write},
+};
+
+use async_trait::async_trait;
+use miette::{IntoDiagnostic, Result};
+use quick_xml::escape::escape;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Node, Selector};
+use tracing::instrument;
+
+use super::retry::Policy;
+
+/// Paragraphs shorter than this are usually captions, bylines or buttons
+const MIN_PARAGRAPH_LENGTH: usize = 25;
+
+const POSITIVE_HINTS: &[&str] = &[
+    "article", "body", "content", "entry", "main", "page", "post", "story", "text",
+];
+const NEGATIVE_HINTS: &[&str] = &[
+    "banner",
+    "byline",
+    "comment",
+    "footer",
+    "header",
+    "menu",
+    "meta",
+    "nav",
+    "newsletter",
+    "related",
+    "share",
+    "sidebar",
+    "social",
+    "sponsor",
+    "widget",
+];
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "aside", "button", "footer", "form", "header", "iframe", "input", "nav", "noscript", "script",
+    "select", "style", "svg", "textarea",
+];
+const VOID_ELEMENTS: &[&str] = &["br", "hr", "img", "wbr"];
+
+#[derive(Debug)]
+pub struct Reqwest {
+    client: reqwest::Client,
+    retry: Policy,
+}
+
+#[async_trait]
+pub trait Repository {
+    /// Download the page at `url` and extract the main article body as HTML
+    async fn full_article(&self, url: &Url) -> Result<Option<String>>;
+}
+
+#[async_trait]
+impl Repository for Reqwest {
+    #[instrument]
+    async fn full_article(&self, url: &Url) -> Result<Option<String>> {
+        let page = self
+            .retry
+            .send(self.client.get(url.clone()))
+            .await
+            .into_diagnostic()?
+            .error_for_status()
+            .into_diagnostic()?
+            .text()
+            .await
+            .into_diagnostic()?;
+
+        Ok(extract(&page))
+    }
+}
+
+impl Reqwest {
+    /// Create a client that downloads article pages
+    #[instrument]
+    pub fn try_new(retry: Policy) -> Result<Self> {
+        let client = reqwest::Client::builder().build().into_diagnostic()?;
+
+        Ok(Self { client, retry })
+    }
+}
+
+/// Find the element most likely to hold the article in an HTML page, and return it stripped of
+/// navigation, comments, scripts and other clutter
+///
+/// This is a simplified version of the scoring used by Arc90's readability: every paragraph
+/// awards points to its parent and grandparent, which are then weighted by their class names and
+/// how much of their text is links.
+#[instrument(skip(html))]
+pub fn extract(html: &str) -> Option<String> {
+    let document = Html::parse_document(html);
+    let paragraphs = Selector::parse("p, pre, td").expect("Infallible");
+    let mut scores = HashMap::new();
+
+    for paragraph in document.select(&paragraphs) {
+        let text = paragraph.text().collect::<String>();
+        let length = text.trim().chars().count();
+        if length < MIN_PARAGRAPH_LENGTH {
+            continue;
+        }
+
+        #[allow(
+            clippy::cast_precision_loss,
+            reason = "paragraphs are nowhere near 2^52 chars"
+        )]
+        let score = 1.0 + text.matches(',').count() as f64 + (length as f64 / 100.0).min(3.0);
+
+        let parent = paragraph.parent().and_then(ElementRef::wrap);
+        let grandparent = parent
+            .and_then(|parent| parent.parent())
+            .and_then(ElementRef::wrap);
+
+        for (ancestor, share) in [(parent, 1.0), (grandparent, 0.5)] {
+            if let Some(ancestor) = ancestor {
+                scores
+                    .entry(ancestor.id())
+                    .or_insert_with(|| (ancestor, initial_score(ancestor)))
+                    .1 += score * share;
+            }
+        }
+    }
+
+    let (best, _) = scores
+        .into_values()
+        .map(|(element, score)| (element, score * (1.0 - link_density(element))))
+        .filter(|(_, score)| *score > 0.0)
+        .max_by(|(_, left), (_, right)| left.total_cmp(right))?;
+
+    let mut article = String::new();
+    render_children(best, &mut article);
+
+    Some(article)
+}
+
+fn initial_score(element: ElementRef<'_>) -> f64 {
+    let tag_score = match element.value().name() {
+        "article" => 10.0,
+        "div" | "main" | "section" => 5.0,
+        "blockquote" | "pre" | "td" => 3.0,
+        "address" | "dl" | "dd" | "dt" | "form" | "li" | "ol" | "ul" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+
+    tag_score + class_weight(element.value())
+}
+
+fn class_weight(element: &scraper::node::Element) -> f64 {
+    let hints = element
+        .classes()
+        .chain(element.id())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    let mut weight = 0.0;
+    if NEGATIVE_HINTS.iter().any(|hint| hints.contains(hint)) {
+        weight -= 25.0;
+    }
+    if POSITIVE_HINTS.iter().any(|hint| hints.contains(hint)) {
+        weight += 25.0;
+    }
+    weight
+}
+
+fn link_density(element: ElementRef<'_>) -> f64 {
+    let links = Selector::parse("a").expect("Infallible");
+    let text_length = element.text().map(str::len).sum::<usize>();
+    if text_length == 0 {
+        return 1.0;
+    }
+
+    let link_length = element
+        .select(&links)
+        .flat_map(|link| link.text())
+        .map(str::len)
+        .sum::<usize>();
+
+    #[allow(
+        clippy::cast_precision_loss,
+        reason = "pages are nowhere near 2^52 bytes"
+    )]
+    let density = link_length as f64 / text_length as f64;
+    density
+}
+
+fn render_children(element: ElementRef<'_>, buf: &mut String) {
+    for child in element.children() {
+        if let Node::Text(text) = child.value() {
+            buf.push_str(&escape(text));
+        }
+
+        let Some(child) = ElementRef::wrap(child) else {
+            continue;
+        };
+        let name = child.value().name();
+        if SKIPPED_ELEMENTS.contains(&name) || class_weight(child.value()) < 0.0 {
+            continue;
+        }
+
+        write!(buf, "<{name}>").expect("Infallible");
+        if !VOID_ELEMENTS.contains(&name) {
+            render_children(child, buf);
+            write!(buf, "</{name}>").expect("Infallible");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+    use wiremock::{
+        matchers::{method, path},
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use super::{extract, Repository, Reqwest};
+    use crate::remote::retry::Policy;
+
+    #[test]
+    fn extracts_the_body_of_a_blog_post() {
+        let article = extract(include_str!("../../tests/fixtures/articles/blog-post.html"))
+            .expect("Expected an article to be found");
+
+        assert!(
+            article.contains("A quick brown fox lived at the edge of the forest"),
+            "Expected the first paragraph in {article}"
+        );
+        assert!(
+            article.contains("jumped over the lazy dog every morning"),
+            "Expected the last paragraph in {article}"
+        );
+        for clutter in [
+            "Archive",
+            "The Badger",
+            "Share this story",
+            "my kids loved it",
+            "All rights reserved",
+            "window.analytics",
+        ] {
+            assert!(
+                !article.contains(clutter),
+                "Expected {clutter:?} to be removed from {article}"
+            );
+        }
+    }
+
+    #[test]
+    fn extracts_the_body_of_a_news_article() {
+        let article = extract(include_str!(
+            "../../tests/fixtures/articles/news-article.html"
+        ))
+        .expect("Expected an article to be found");
+
+        assert!(
+            article.contains("<h1>Owls return to the old oak</h1>"),
+            "Expected the headline in {article}"
+        );
+        assert!(
+            article.contains("like the village had its voice back"),
+            "Expected the quote in {article}"
+        );
+        assert!(
+            article.contains("keep their distance while the owlets fledge"),
+            "Expected the last paragraph in {article}"
+        );
+        assert!(
+            !article.contains("Sign up to our newsletter"),
+            "Expected the newsletter form to be removed from {article}"
+        );
+        assert!(
+            !article.contains("Weather"),
+            "Expected the navigation to be removed from {article}"
+        );
+    }
+
+    #[test]
+    fn extracted_articles_can_be_cleaned_up_by_html2text() {
+        let article = extract(include_str!(
+            "../../tests/fixtures/articles/news-article.html"
+        ))
+        .expect("Expected an article to be found");
+
+        let text = html2text::from_read(article.as_bytes(), usize::MAX);
+
+        assert!(
+            text.contains("\"We heard them calling at dusk"),
+            "Expected the quote to be unescaped in {text}"
+        );
+    }
+
+    #[test]
+    fn pages_without_an_article_have_nothing_extracted() {
+        assert_eq!(
+            extract(include_str!(
+                "../../tests/fixtures/articles/no-content.html"
+            )),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn full_article_downloads_and_extracts_the_page() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/fox"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(include_str!("../../tests/fixtures/articles/blog-post.html")),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let url = Url::parse(&format!("{}/fox", server.uri())).expect("Invalid URL");
+        let article = Reqwest::try_new(Policy::default())
+            .expect("Failed to create client")
+            .full_article(&url)
+            .await
+            .expect("Failed to download article")
+            .expect("Expected an article to be found");
+
+        assert!(
+            article.contains("A quick brown fox lived at the edge of the forest"),
+            "Expected the article body in {article}"
+        );
+    }
+}
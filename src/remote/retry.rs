@@ -0,0 +1,442 @@
+use std::{
+    fmt::Display,
+    future::Future,
+    num::NonZeroU32,
+    time::{Duration, SystemTime},
+};
+
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
+
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_mins(2);
+pub const DEFAULT_MAX_RETRY_WAIT: Duration = Duration::from_mins(5);
+
+/// How to try requests to remote services again when they fail in a way that might not happen
+/// next time, such as being rate limited, a server error, or a timeout
+///
+/// Waits double after each failed attempt, up to `max_backoff`, with some randomness so clients
+/// that failed together don't all try again together. A `Retry-After` from the server is waited
+/// for, up to `max_retry_wait`, so a bogus one can't hold a run up for hours.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Policy {
+    /// How many times a request is sent before giving up, including the first
+    pub max_attempts: NonZeroU32,
+    /// How long to wait after the first failure
+    pub initial_backoff: Duration,
+    /// The longest to wait between attempts, unless the server asks for longer
+    pub max_backoff: Duration,
+    /// The longest to wait when the server asks for longer
+    pub max_retry_wait: Duration,
+    /// How long each attempt may take before it is abandoned
+    pub timeout: Duration,
+}
+
+/// Why an attempt failed
+#[derive(Debug)]
+pub enum Failure<E> {
+    /// Trying again might work, after `retry_after` if the service said when
+    Transient {
+        error: E,
+        retry_after: Option<Duration>,
+    },
+    /// Trying again would fail the same way
+    Permanent(E),
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            max_attempts: NonZeroU32::new(DEFAULT_MAX_ATTEMPTS).expect("Infallible"),
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_mins(1),
+            max_retry_wait: DEFAULT_MAX_RETRY_WAIT,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl Policy {
+    /// Send `request`, sending it again if it times out, can't connect, is rate limited or gets a
+    /// server error
+    ///
+    /// Once out of attempts the last response is returned as it is, error status and all, so
+    /// callers can report what the service said.
+    pub async fn send(&self, request: RequestBuilder) -> reqwest::Result<Response> {
+        let mut attempt = 1;
+        loop {
+            // Requests with streamed bodies can only be sent once
+            let Some(this_attempt) = request.try_clone() else {
+                return request.timeout(self.timeout).send().await;
+            };
+            let last_attempt = attempt >= self.max_attempts.get();
+
+            match this_attempt.timeout(self.timeout).send().await {
+                Ok(response) if !last_attempt && is_transient_status(response.status()) => {
+                    tracing::warn!(
+                        "Attempt {} of {} to {} got {}",
+                        attempt,
+                        self.max_attempts,
+                        response.url(),
+                        response.status()
+                    );
+                    self.wait(attempt, retry_after(&response)).await;
+                }
+                Ok(response) => return Ok(response),
+                Err(error) if !last_attempt && is_transient_error(&error) => {
+                    tracing::warn!(
+                        "Attempt {} of {} failed: {}",
+                        attempt,
+                        self.max_attempts,
+                        error
+                    );
+                    self.wait(attempt, None).await;
+                }
+                Err(error) => return Err(error),
+            }
+            attempt += 1;
+        }
+    }
+
+    /// Run `operation` until it succeeds, fails permanently, or is out of attempts
+    pub async fn run<T, E, F, Fut>(&self, mut operation: F) -> Result<T, E>
+    where
+        E: Display,
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T, Failure<E>>> + Send,
+    {
+        let mut attempt = 1;
+        loop {
+            match operation().await {
+                Ok(value) => return Ok(value),
+                Err(Failure::Transient { error, retry_after })
+                    if attempt < self.max_attempts.get() =>
+                {
+                    tracing::warn!(
+                        "Attempt {} of {} failed: {}",
+                        attempt,
+                        self.max_attempts,
+                        error
+                    );
+                    self.wait(attempt, retry_after).await;
+                }
+                Err(Failure::Transient { error, .. } | Failure::Permanent(error)) => {
+                    return Err(error)
+                }
+            }
+            attempt += 1;
+        }
+    }
+
+    async fn wait(&self, attempt: u32, retry_after: Option<Duration>) {
+        tokio::time::sleep(self.delay(attempt, retry_after)).await;
+    }
+
+    /// What the server asked for, but no longer than `max_retry_wait`, or else the backoff
+    fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        let Some(retry_after) = retry_after else {
+            return self.backoff(attempt);
+        };
+        if retry_after > self.max_retry_wait {
+            tracing::warn!(
+                "Asked to wait {}, waiting {} instead",
+                humantime::format_duration(retry_after),
+                humantime::format_duration(self.max_retry_wait)
+            );
+        }
+        retry_after.min(self.max_retry_wait)
+    }
+
+    /// Somewhere between half and all of the doubled wait, so it never drops to nothing
+    fn backoff(&self, attempt: u32) -> Duration {
+        let doubled = self
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_backoff);
+        let half = doubled / 2;
+
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED
+}
+
+/// Failures to get any response at all, rather than an unhappy one
+fn is_transient_error(error: &reqwest::Error) -> bool {
+    error.is_timeout() || error.is_connect() || error.is_request()
+}
+
+/// How long the server asked us to wait, given either in seconds or as a date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    value.parse().map(Duration::from_secs).ok().or_else(|| {
+        httpdate::parse_http_date(value)
+            .ok()
+            .map(|date| date.duration_since(SystemTime::now()).unwrap_or_default())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroU32,
+        sync::atomic::{AtomicU32, Ordering},
+        time::{Duration, Instant},
+    };
+
+    use miette::miette;
+    use wiremock::{
+        matchers::{method, path},
+        Mock,
+        MockServer,
+        ResponseTemplate,
+    };
+
+    use super::{Failure, Policy};
+
+    fn policy(max_attempts: u32) -> Policy {
+        Policy {
+            max_attempts: NonZeroU32::new(max_attempts).expect("Attempts must not be zero"),
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+            max_retry_wait: Duration::from_secs(2),
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Answer with `status` for the first `times` requests, and then succeed
+    async fn failing(server: &MockServer, status: ResponseTemplate, times: u64) {
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(status)
+            .up_to_n_times(times)
+            .expect(times)
+            .mount(server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("Fine"))
+            .mount(server)
+            .await;
+    }
+
+    async fn get(server: &MockServer, policy: Policy) -> reqwest::Result<reqwest::Response> {
+        policy.send(reqwest::Client::new().get(server.uri())).await
+    }
+
+    #[tokio::test]
+    async fn rate_limits_are_tried_again() {
+        let server = MockServer::start().await;
+        failing(&server, ResponseTemplate::new(429), 2).await;
+
+        let response = get(&server, policy(3)).await.expect("Failed to send");
+
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_tried_again() {
+        let server = MockServer::start().await;
+        failing(&server, ResponseTemplate::new(503), 1).await;
+
+        let response = get(&server, policy(2)).await.expect("Failed to send");
+
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_tried_again() {
+        let server = MockServer::start().await;
+        failing(&server, ResponseTemplate::new(401), 1).await;
+
+        let response = get(&server, policy(3)).await.expect("Failed to send");
+
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn the_last_response_is_returned_when_out_of_attempts() {
+        let server = MockServer::start().await;
+        failing(&server, ResponseTemplate::new(500), 2).await;
+
+        let response = get(&server, policy(2)).await.expect("Failed to send");
+
+        assert_eq!(response.status(), 500);
+    }
+
+    #[tokio::test]
+    async fn timeouts_are_tried_again() {
+        let server = MockServer::start().await;
+        failing(
+            &server,
+            ResponseTemplate::new(200).set_delay(Duration::from_secs(2)),
+            1,
+        )
+        .await;
+
+        let response = get(
+            &server,
+            Policy {
+                timeout: Duration::from_millis(200),
+                ..policy(2)
+            },
+        )
+        .await
+        .expect("Failed to send");
+
+        assert_eq!(response.text().await.expect("Failed to read"), "Fine");
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_waited_for() {
+        let server = MockServer::start().await;
+        failing(
+            &server,
+            ResponseTemplate::new(429).insert_header("Retry-After", "1"),
+            1,
+        )
+        .await;
+
+        let started = Instant::now();
+        get(&server, policy(2)).await.expect("Failed to send");
+
+        assert!(
+            started.elapsed() >= Duration::from_secs(1),
+            "Expected to wait for the Retry-After"
+        );
+    }
+
+    #[tokio::test]
+    async fn retry_after_can_be_a_date() {
+        let server = MockServer::start().await;
+        failing(
+            &server,
+            ResponseTemplate::new(503).insert_header(
+                "Retry-After",
+                httpdate::fmt_http_date(std::time::SystemTime::now()).as_str(),
+            ),
+            1,
+        )
+        .await;
+
+        let response = get(&server, policy(2)).await.expect("Failed to send");
+
+        assert_eq!(response.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn long_retry_afters_are_cut_short() {
+        let server = MockServer::start().await;
+        failing(
+            &server,
+            ResponseTemplate::new(429).insert_header("Retry-After", "86400"),
+            1,
+        )
+        .await;
+
+        let policy = Policy {
+            max_retry_wait: Duration::from_millis(10),
+            ..policy(2)
+        };
+        let response = tokio::time::timeout(Duration::from_secs(5), get(&server, policy))
+            .await
+            .expect("Expected the wait to be cut short")
+            .expect("Failed to send");
+
+        assert_eq!(response.status(), 200);
+    }
+
+    #[test]
+    fn retry_afters_are_capped_at_the_maximum() {
+        let policy = Policy {
+            max_retry_wait: Duration::from_mins(5),
+            ..policy(2)
+        };
+
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_hours(24))),
+            Duration::from_mins(5)
+        );
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_secs(30))),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = Policy {
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            ..policy(10)
+        };
+
+        for (attempt, doubled) in [(1, 1), (2, 2), (3, 4), (4, 5), (20, 5)] {
+            let backoff = policy.backoff(attempt);
+            let doubled = Duration::from_secs(doubled);
+
+            assert!(
+                backoff >= doubled / 2 && backoff <= doubled,
+                "Attempt {attempt} waited {backoff:?}, expected up to {doubled:?}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn run_tries_transient_failures_again() {
+        let attempts = AtomicU32::new(0);
+
+        let result = policy(3)
+            .run(|| async {
+                if attempts.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Err(Failure::Transient {
+                        error: miette!("Busy"),
+                        retry_after: None,
+                    })
+                } else {
+                    Ok("Done")
+                }
+            })
+            .await;
+
+        assert_eq!(result.expect("Expected to succeed"), "Done");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn run_gives_up_when_out_of_attempts() {
+        let attempts = AtomicU32::new(0);
+
+        let result: miette::Result<()> = policy(2)
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(Failure::Transient {
+                    error: miette!("Busy"),
+                    retry_after: None,
+                })
+            })
+            .await;
+
+        assert!(result.is_err(), "Expected to give up");
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn run_does_not_try_permanent_failures_again() {
+        let attempts = AtomicU32::new(0);
+
+        let result: miette::Result<()> = policy(3)
+            .run(|| async {
+                attempts.fetch_add(1, Ordering::SeqCst);
+                Err(Failure::Permanent(miette!("Wrong key")))
+            })
+            .await;
+
+        assert!(result.is_err(), "Expected to fail");
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
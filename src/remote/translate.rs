@@ -0,0 +1,138 @@
+use std::fmt::{Debug, Display, Formatter};
+
+use async_trait::async_trait;
+use miette::Result;
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use super::{deepl::Deepl, google_translate::GoogleTranslate};
+
+/// A language code, such as "en" or "pt-BR"
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Language(String);
+
+impl Language {
+    /// Whether both codes are for the same language, ignoring case and regional variants so
+    /// "en-GB" matches "en"
+    pub fn matches(&self, other: &Self) -> bool {
+        self.primary().eq_ignore_ascii_case(other.primary())
+    }
+
+    /// The language without any region, "en" for "en-GB"
+    pub fn primary(&self) -> &str {
+        self.0.split(['-', '_']).next().unwrap_or_default()
+    }
+}
+
+impl Display for Language {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<String> for Language {
+    fn from(v: String) -> Self {
+        Self(v)
+    }
+}
+
+impl From<Language> for String {
+    fn from(v: Language) -> Self {
+        v.0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Translation {
+    pub text: String,
+    /// The language the service thinks the text was written in, if it says
+    pub detected_source_language: Option<Language>,
+}
+
+#[async_trait]
+pub trait Translator {
+    async fn translate<
+        T: Into<String> + Debug + Sync + Send,
+        L: Into<Language> + Debug + Sync + Send,
+    >(
+        &self,
+        text: T,
+        target: L,
+    ) -> Result<Translation>;
+}
+
+/// Whichever translator was picked on the command line
+#[derive(Debug)]
+pub enum AnyTranslator {
+    GoogleTranslate(GoogleTranslate),
+    Deepl(Deepl),
+}
+
+#[async_trait]
+impl Translator for AnyTranslator {
+    #[instrument]
+    async fn translate<
+        T: Into<String> + Debug + Sync + Send,
+        L: Into<Language> + Debug + Sync + Send,
+    >(
+        &self,
+        text: T,
+        target: L,
+    ) -> Result<Translation> {
+        match self {
+            Self::GoogleTranslate(translator) => translator.translate(text, target).await,
+            Self::Deepl(translator) => translator.translate(text, target).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Language;
+
+    #[test]
+    fn language_is_a_string_in_json() {
+        let language: Language = "en".to_string().into();
+        assert_eq!(
+            serde_json::to_string(&language).expect("Failed to serialize"),
+            "\"en\"".to_string()
+        );
+    }
+
+    #[test]
+    fn language_can_be_made_from_string() {
+        let language: Language = "en".to_string().into();
+        assert_eq!(language, Language("en".to_string()));
+    }
+
+    #[test]
+    fn language_implements_display() {
+        let language = Language("en".to_string());
+        assert_eq!(language.to_string(), "en");
+    }
+
+    #[test]
+    fn languages_match_across_regions_and_case() {
+        let english = Language("en".to_string());
+
+        assert!(
+            english.matches(&Language("en-GB".to_string())),
+            "Expected en to match en-GB"
+        );
+        assert!(
+            english.matches(&Language("EN_us".to_string())),
+            "Expected en to match EN_us"
+        );
+        assert!(
+            !english.matches(&Language("de".to_string())),
+            "Expected en not to match de"
+        );
+    }
+
+    #[test]
+    fn language_implements_into_string() {
+        let language: String = Language("en".to_string()).into();
+        assert_eq!(language, "en".to_string());
+    }
+}
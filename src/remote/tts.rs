@@ -0,0 +1,163 @@
+use std::fmt::Debug;
+
+use async_trait::async_trait;
+use futures::stream;
+use miette::{miette, Result};
+use tracing::instrument;
+
+use super::{
+    super::io::{
+        audio::{Audio as _, VecU8A},
+        local_tts::{self, Local},
+        stream::Streaming,
+    },
+    elevenlabs::{self, Repository as _, Settings, Voice},
+};
+
+/// Something that reads text aloud, with a voice and settings picked up front
+#[async_trait]
+pub trait Tts {
+    /// Who is reading, for the artist in metadata
+    fn voice(&self) -> String;
+
+    /// The most characters read at once, longer text is read in pieces
+    fn max_characters(&self) -> usize;
+
+    async fn text_to_speech(&self, text: String) -> Result<VecU8A>;
+
+    /// Start reading, returning the audio while it is still arriving where the engine can
+    async fn stream_text_to_speech(&self, text: String) -> Result<Streaming>;
+}
+
+/// The `ElevenLabs` service reading with one voice and one set of settings
+#[derive(Debug)]
+pub struct Elevenlabs {
+    client: elevenlabs::Reqwest,
+    voice: Voice,
+    settings: Settings,
+}
+
+impl Elevenlabs {
+    pub const fn new(client: elevenlabs::Reqwest, voice: Voice, settings: Settings) -> Self {
+        Self {
+            client,
+            voice,
+            settings,
+        }
+    }
+
+    /// The same service and settings reading with `voice`, which can be an ID or a name
+    #[instrument]
+    pub async fn with_voice(&self, voice: Voice) -> Result<Self> {
+        Ok(Self {
+            client: self.client.clone(),
+            voice: self.client.resolve_voice(voice).await?,
+            settings: self.settings.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Tts for Elevenlabs {
+    fn voice(&self) -> String {
+        self.voice.to_string()
+    }
+
+    fn max_characters(&self) -> usize {
+        elevenlabs::MAX_CHARACTERS
+    }
+
+    #[instrument]
+    async fn text_to_speech(&self, text: String) -> Result<VecU8A> {
+        self.client
+            .text_to_speech(self.voice.clone(), &self.settings, text)
+            .await
+    }
+
+    #[instrument]
+    async fn stream_text_to_speech(&self, text: String) -> Result<Streaming> {
+        self.client
+            .stream_text_to_speech(self.voice.clone(), &self.settings, text)
+            .await
+    }
+}
+
+#[async_trait]
+impl Tts for Local {
+    fn voice(&self) -> String {
+        Self::voice(self)
+    }
+
+    fn max_characters(&self) -> usize {
+        local_tts::MAX_CHARACTERS
+    }
+
+    #[instrument]
+    async fn text_to_speech(&self, text: String) -> Result<VecU8A> {
+        Self::text_to_speech(self, &text).await
+    }
+
+    /// Local engines are quick enough that the audio is made in full, then played
+    #[instrument]
+    async fn stream_text_to_speech(&self, text: String) -> Result<Streaming> {
+        let audio = Self::text_to_speech(self, &text).await?;
+        let format = audio.format();
+        Ok(Streaming::new(
+            stream::iter([Ok::<_, String>(audio.into_bytes())]),
+            format,
+        ))
+    }
+}
+
+/// Whichever speech engine was picked on the command line
+#[derive(Debug)]
+pub enum AnyTts {
+    Elevenlabs(Box<Elevenlabs>),
+    Local(Local),
+}
+
+impl AnyTts {
+    /// The same engine and settings reading with another `voice`, which only `ElevenLabs` can do
+    #[instrument]
+    pub async fn with_voice(&self, voice: Voice) -> Result<Self> {
+        match self {
+            Self::Elevenlabs(tts) => Ok(Self::Elevenlabs(Box::new(tts.with_voice(voice).await?))),
+            Self::Local(_) => Err(miette!(
+                "Characters can only have voices of their own when reading with ElevenLabs"
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl Tts for AnyTts {
+    fn voice(&self) -> String {
+        match self {
+            Self::Elevenlabs(tts) => tts.voice(),
+            Self::Local(tts) => Tts::voice(tts),
+        }
+    }
+
+    fn max_characters(&self) -> usize {
+        match self {
+            Self::Elevenlabs(tts) => tts.max_characters(),
+            Self::Local(tts) => tts.max_characters(),
+        }
+    }
+
+    #[instrument]
+    async fn text_to_speech(&self, text: String) -> Result<VecU8A> {
+        match self {
+            Self::Elevenlabs(tts) => tts.text_to_speech(text).await,
+            Self::Local(tts) => Tts::text_to_speech(tts, text).await,
+        }
+    }
+
+    #[instrument]
+    async fn stream_text_to_speech(&self, text: String) -> Result<Streaming> {
+        match self {
+            Self::Elevenlabs(tts) => tts.stream_text_to_speech(text).await,
+            Self::Local(tts) => tts.stream_text_to_speech(text).await,
+        }
+    }
+}
@@ -0,0 +1,379 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::remote::translate::Language;
+
+const PARAGRAPH_SEPARATOR: &str = "\n\n";
+const SENTENCE_SEPARATOR: &str = " ";
+const WORD_SEPARATOR: &str = " ";
+
+/// Words that end in a full stop without ending the sentence, lower case and without the final
+/// full stop
+const ENGLISH_ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "sr", "jr", "st", "mt", "vs", "fig", "approx", "dept", "inc",
+    "ltd", "co", "e.g", "i.e", "jan", "feb", "mar", "apr", "jun", "jul", "aug", "sep", "sept",
+    "oct", "nov", "dec",
+];
+const GERMAN_ABBREVIATIONS: &[&str] = &[
+    "dr", "prof", "hr", "fr", "nr", "str", "bzw", "ca", "vgl", "z.b", "d.h", "u.a", "s", "evtl",
+    "ggf", "inkl", "jan", "feb", "aug", "sept", "okt", "nov", "dez",
+];
+const FRENCH_ABBREVIATIONS: &[&str] = &[
+    "m", "mm", "mme", "mlle", "dr", "pr", "st", "ste", "env", "cf", "p.ex", "janv", "févr", "avr",
+    "juil", "sept", "oct", "nov", "déc",
+];
+const SPANISH_ABBREVIATIONS: &[&str] = &[
+    "sr", "sra", "srta", "dr", "dra", "ud", "uds", "prof", "p.ej", "aprox", "ene", "feb", "abr",
+    "ago", "sept", "oct", "nov", "dic",
+];
+const DUTCH_ABBREVIATIONS: &[&str] = &[
+    "dhr", "mevr", "dr", "prof", "bijv", "bv", "o.a", "d.w.z", "jan", "feb", "aug", "sept", "okt",
+    "nov", "dec",
+];
+
+/// Split text into chunks of at most `max_length` characters, for text-to-speech services that
+/// limit how much they will read at once
+///
+/// Chunks end at paragraph breaks where possible, then at the end of a sentence, then between
+/// words. A word longer than a whole chunk is the only thing that is ever cut. `language` picks
+/// which abbreviations don't end a sentence, defaulting to English.
+pub fn chunk(text: &str, max_length: usize, language: Option<&Language>) -> Vec<String> {
+    let abbreviations = abbreviations(language);
+    let mut chunks = Chunks::new(max_length.max(1));
+
+    for paragraph in paragraphs(text) {
+        if chunks.push(paragraph, PARAGRAPH_SEPARATOR) {
+            continue;
+        }
+
+        for sentence in sentences(paragraph, abbreviations) {
+            if chunks.push(sentence, SENTENCE_SEPARATOR) {
+                continue;
+            }
+
+            for word in sentence.split_whitespace() {
+                if chunks.push(word, WORD_SEPARATOR) {
+                    continue;
+                }
+
+                chunks.push_cut(word);
+            }
+        }
+    }
+
+    chunks.finish()
+}
+
+/// Chunks of text being packed as full as they can be without going over the limit
+struct Chunks {
+    max_length: usize,
+    finished: Vec<String>,
+    current: String,
+    current_length: usize,
+}
+
+impl Chunks {
+    const fn new(max_length: usize) -> Self {
+        Self {
+            max_length,
+            finished: vec![],
+            current: String::new(),
+            current_length: 0,
+        }
+    }
+
+    /// Add `piece` to the current chunk, or start a new one if it doesn't fit. Returns false,
+    /// without adding anything, if it would not fit even in a chunk of its own.
+    fn push(&mut self, piece: &str, separator: &str) -> bool {
+        let length = piece.chars().count();
+        if length > self.max_length {
+            return false;
+        }
+
+        let separator_length = separator.chars().count();
+        if self.current_length > 0
+            && self.current_length + separator_length + length > self.max_length
+        {
+            self.flush();
+        }
+        if self.current_length > 0 {
+            self.current.push_str(separator);
+            self.current_length += separator_length;
+        }
+        self.current.push_str(piece);
+        self.current_length += length;
+
+        true
+    }
+
+    /// Cut a word that is too long for any chunk, keeping characters that render as one together
+    /// unless even they don't fit
+    fn push_cut(&mut self, word: &str) {
+        self.flush();
+        let mut separator = "";
+
+        for grapheme in word.graphemes(true) {
+            if self.push(grapheme, separator) {
+                separator = "";
+                continue;
+            }
+
+            for character in grapheme.chars() {
+                self.push(character.encode_utf8(&mut [0; 4]), separator);
+                separator = "";
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.current_length > 0 {
+            self.finished.push(std::mem::take(&mut self.current));
+            self.current_length = 0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<String> {
+        self.flush();
+        self.finished
+    }
+}
+
+/// Runs of lines separated by blank lines, trimmed
+fn paragraphs(text: &str) -> Vec<&str> {
+    let mut paragraphs = vec![];
+    let mut start = None;
+    let mut end = 0;
+    let mut offset = 0;
+
+    for line in text.split_inclusive('\n') {
+        if line.trim().is_empty() {
+            if let Some(start) = start.take() {
+                paragraphs.push(text[start..end].trim());
+            }
+        } else {
+            start.get_or_insert(offset);
+            end = offset + line.len();
+        }
+        offset += line.len();
+    }
+    if let Some(start) = start {
+        paragraphs.push(text[start..end].trim());
+    }
+
+    paragraphs
+}
+
+/// Sentences, found with the Unicode sentence boundary rules, but not ending at abbreviations
+/// such as "Dr." or initials such as "J."
+fn sentences<'a>(paragraph: &'a str, abbreviations: &[&str]) -> Vec<&'a str> {
+    let mut sentences = vec![];
+    let mut start = 0;
+
+    for (offset, sentence) in paragraph.split_sentence_bound_indices() {
+        let end = offset + sentence.len();
+        if end < paragraph.len() && ends_with_abbreviation(sentence, abbreviations) {
+            continue;
+        }
+
+        sentences.push(paragraph[start..end].trim());
+        start = end;
+    }
+    if start < paragraph.len() {
+        sentences.push(paragraph[start..].trim());
+    }
+
+    sentences.retain(|sentence| !sentence.is_empty());
+    sentences
+}
+
+fn ends_with_abbreviation(sentence: &str, abbreviations: &[&str]) -> bool {
+    let Some(word) = sentence.split_whitespace().next_back() else {
+        return false;
+    };
+    let Some(word) = word.strip_suffix('.') else {
+        return false;
+    };
+    let word = word.trim_start_matches(|character: char| !character.is_alphanumeric());
+    let is_initial = word.chars().count() == 1 && word.chars().all(char::is_uppercase);
+
+    is_initial || abbreviations.contains(&word.to_lowercase().as_str())
+}
+
+fn abbreviations(language: Option<&Language>) -> &'static [&'static str] {
+    match language
+        .map(|language| language.primary().to_lowercase())
+        .as_deref()
+    {
+        Some("de") => GERMAN_ABBREVIATIONS,
+        Some("fr") => FRENCH_ABBREVIATIONS,
+        Some("es") => SPANISH_ABBREVIATIONS,
+        Some("nl") => DUTCH_ABBREVIATIONS,
+        _ => ENGLISH_ABBREVIATIONS,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::chunk;
+    use crate::remote::translate::Language;
+
+    fn english(text: &str, max_length: usize) -> Vec<String> {
+        chunk(text, max_length, None)
+    }
+
+    fn non_whitespace(text: &str) -> String {
+        text.chars()
+            .filter(|character| !character.is_whitespace())
+            .collect()
+    }
+
+    #[test]
+    fn short_text_is_one_chunk() {
+        assert_eq!(
+            english("The quick brown fox jumps over the lazy dog.", 100),
+            vec!["The quick brown fox jumps over the lazy dog."]
+        );
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        assert!(english(" \n\n \t", 100).is_empty(), "Expected no chunks");
+    }
+
+    #[test]
+    fn paragraphs_are_kept_together() {
+        assert_eq!(
+            english(
+                "The fox jumps. It is quick.\n\nThe dog sleeps. It is lazy.\n\nThe end.",
+                40
+            ),
+            vec![
+                "The fox jumps. It is quick.",
+                "The dog sleeps. It is lazy.\n\nThe end."
+            ]
+        );
+    }
+
+    #[test]
+    fn long_paragraphs_end_at_sentences() {
+        assert_eq!(
+            english("Is the fox quick? Yes! The dog is lazy though.", 20),
+            vec!["Is the fox quick?", "Yes! The dog is lazy", "though."]
+        );
+    }
+
+    #[test]
+    fn abbreviations_do_not_end_sentences() {
+        assert_eq!(
+            english("Dr. Fox met Mrs. Owl at St. Paul's. They talked.", 40),
+            vec!["Dr. Fox met Mrs. Owl at St. Paul's.", "They talked."]
+        );
+    }
+
+    #[test]
+    fn words_that_look_like_abbreviations_end_sentences() {
+        assert_eq!(
+            english("The owl said no. The fox left.", 20),
+            vec!["The owl said no.", "The fox left."]
+        );
+    }
+
+    #[test]
+    fn initials_do_not_end_sentences() {
+        assert_eq!(
+            english("The fox met J. R. Owl. They talked.", 25),
+            vec!["The fox met J. R. Owl.", "They talked."]
+        );
+    }
+
+    #[test]
+    fn abbreviations_depend_on_the_language() {
+        let german = Language::from("de-DE".to_string());
+
+        assert_eq!(
+            chunk(
+                "Der Fuchs mag z.B. Beeren. Der Hund schläft.",
+                30,
+                Some(&german)
+            ),
+            vec!["Der Fuchs mag z.B. Beeren.", "Der Hund schläft."]
+        );
+    }
+
+    #[test]
+    fn decimals_and_urls_do_not_end_sentences() {
+        assert_eq!(
+            english("The fox ran 3.5 km. See example.com/fox for more.", 30),
+            vec!["The fox ran 3.5 km.", "See example.com/fox for more."]
+        );
+    }
+
+    #[test]
+    fn length_is_measured_in_characters() {
+        let text = "Ünïcödé ünïcödé.";
+
+        assert_eq!(english(text, 16), vec![text]);
+    }
+
+    #[test]
+    fn long_words_are_cut() {
+        assert_eq!(english("a abcdefgh", 3), vec!["a", "abc", "def", "gh"]);
+    }
+
+    #[test]
+    fn long_words_are_cut_between_graphemes() {
+        assert_eq!(
+            english("e\u{301}e\u{301}e\u{301}", 4),
+            vec!["e\u{301}e\u{301}", "e\u{301}"]
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn chunks_are_never_too_long(text in "[\\PC\\n]*", max_length in 1_usize..50) {
+            for chunk in english(&text, max_length) {
+                prop_assert!(
+                    chunk.chars().count() <= max_length,
+                    "{:?} is longer than {}",
+                    chunk,
+                    max_length
+                );
+            }
+        }
+
+        #[test]
+        fn chunks_are_never_blank(text in "[\\PC\\n]*", max_length in 1_usize..50) {
+            for chunk in english(&text, max_length) {
+                prop_assert!(!chunk.trim().is_empty(), "Found a blank chunk");
+            }
+        }
+
+        #[test]
+        fn chunks_keep_all_the_text(text in "[\\PC\\n]*", max_length in 1_usize..50) {
+            prop_assert_eq!(
+                non_whitespace(&english(&text, max_length).concat()),
+                non_whitespace(&text)
+            );
+        }
+
+        #[test]
+        fn sentences_fit_in_chunks_are_not_split(
+            // The last word is longer than any abbreviation, so every sentence really ends
+            sentences in prop::collection::vec(
+                "[A-Z][a-z]{1,8}( [a-z]{1,8}){0,4} [a-z]{7,9}[.!?]",
+                1..10,
+            ),
+        ) {
+            let text = sentences.join(" ");
+
+            for chunk in english(&text, 60) {
+                prop_assert!(
+                    chunk.ends_with(['.', '!', '?']),
+                    "{:?} does not end at a sentence",
+                    chunk
+                );
+            }
+        }
+    }
+}
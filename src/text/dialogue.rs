@@ -0,0 +1,141 @@
+/// Asks for the story as a script, so each part can be read by whoever speaks it
+pub const DIRECTION: &str = "Write the story as a script. Start every line with who speaks it \
+                             and a colon, such as \"Narrator: Once upon a time\" or \"Fox: \
+                             Hello\", with everything that isn't said by a character read by \
+                             the Narrator. Don't add any other formatting.";
+
+/// Who reads everything that isn't said by a character
+pub const NARRATOR: &str = "Narrator";
+
+/// Longest name taken to be a speaker, anything longer is more likely a sentence with a colon
+const MAX_NAME_LENGTH: usize = 40;
+const MAX_NAME_WORDS: usize = 3;
+
+/// Part of a script read by one speaker
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+    /// The character speaking, or nobody for the narrator
+    pub speaker: Option<String>,
+    pub text: String,
+}
+
+/// Split a script into who says what, joining lines said by the same speaker one after another
+///
+/// Lines that don't start with a speaker carry on from the line before, so a speech can run over
+/// several paragraphs, and anything before the first speaker is narrated.
+pub fn lines(script: &str) -> Vec<Line> {
+    let mut lines: Vec<Line> = vec![];
+    for line in script
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let (speaker, text) = match speaker(line) {
+            Some((speaker, text)) => (speaker, text),
+            None => (
+                lines.last().and_then(|line| line.speaker.clone()),
+                line.to_string(),
+            ),
+        };
+        if text.is_empty() {
+            continue;
+        }
+
+        match lines.last_mut() {
+            Some(last) if last.speaker == speaker => {
+                last.text.push('\n');
+                last.text.push_str(&text);
+            }
+            _ => lines.push(Line { speaker, text }),
+        }
+    }
+    lines
+}
+
+/// The speaker a line starts with, if it does, and what they say
+///
+/// Names can be in bold, as models like to write "**Fox:** Hello".
+fn speaker(line: &str) -> Option<(Option<String>, String)> {
+    let (name, text) = line.split_once(':')?;
+    let name = name.trim().trim_matches('*').trim();
+    let text = text.trim().trim_start_matches('*').trim();
+
+    let is_name = name.chars().next().is_some_and(char::is_uppercase)
+        && name.len() <= MAX_NAME_LENGTH
+        && name.split_whitespace().count() <= MAX_NAME_WORDS
+        && !name.contains(['.', '!', '?', '"', ',']);
+    if !is_name {
+        return None;
+    }
+
+    let speaker = (!name.eq_ignore_ascii_case(NARRATOR)).then(|| name.to_string());
+    Some((speaker, text.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lines, Line};
+
+    fn line(speaker: Option<&str>, text: &str) -> Line {
+        Line {
+            speaker: speaker.map(ToString::to_string),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn speakers_are_split_from_what_they_say() {
+        let script = "Narrator: Once upon a time, a fox met a crow.\n\nFox: Hello, crow!\nCrow: \
+                      Hello, fox.";
+
+        assert_eq!(
+            lines(script),
+            vec![
+                line(None, "Once upon a time, a fox met a crow."),
+                line(Some("Fox"), "Hello, crow!"),
+                line(Some("Crow"), "Hello, fox."),
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_without_a_speaker_carry_on() {
+        let script = "Some text before anyone speaks\nFox: Hello.\nHow are you?";
+
+        assert_eq!(
+            lines(script),
+            vec![
+                line(None, "Some text before anyone speaks"),
+                line(Some("Fox"), "Hello.\nHow are you?"),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_same_speaker_is_joined() {
+        let script = "Narrator: It was dark.\nNARRATOR: And cold.";
+
+        assert_eq!(lines(script), vec![line(None, "It was dark.\nAnd cold.")]);
+    }
+
+    #[test]
+    fn bold_names_are_speakers() {
+        assert_eq!(
+            lines("**Old Owl:** Who goes there?"),
+            vec![line(Some("Old Owl"), "Who goes there?")]
+        );
+    }
+
+    #[test]
+    fn sentences_with_colons_are_not_speakers() {
+        let script = "Fox: Listen.\nThe crow said this: the cheese is mine.";
+
+        assert_eq!(
+            lines(script),
+            vec![line(
+                Some("Fox"),
+                "Listen.\nThe crow said this: the cheese is mine."
+            )]
+        );
+    }
+}
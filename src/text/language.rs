@@ -0,0 +1,42 @@
+use crate::remote::translate::Language;
+
+/// Guess the language of some text locally, only answering when the guess is reliable
+pub fn detect(text: &str) -> Option<Language> {
+    let info = whatlang::detect(text).filter(whatlang::Info::is_reliable)?;
+    let code = isolang::Language::from_639_3(info.lang().code())?.to_639_1()?;
+
+    Some(Language::from(code.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::detect;
+    use crate::remote::translate::Language;
+
+    #[test]
+    fn detects_english() {
+        assert_eq!(
+            detect(
+                "The quick brown fox jumps over the lazy dog, and then it runs back into the \
+                 forest where it lives with its family."
+            ),
+            Some(Language::from("en".to_string()))
+        );
+    }
+
+    #[test]
+    fn detects_german() {
+        assert_eq!(
+            detect(
+                "Der schnelle braune Fuchs springt über den faulen Hund und läuft dann zurück in \
+                 den Wald, wo er mit seiner Familie lebt."
+            ),
+            Some(Language::from("de".to_string()))
+        );
+    }
+
+    #[test]
+    fn does_not_guess_from_too_little_text() {
+        assert_eq!(detect("Fox"), None);
+    }
+}